@@ -1,8 +1,20 @@
 mod modules;
 
-use modules::app::AppState;
+use clap::Parser;
+use modules::app::{AppState, LogLevel};
+use modules::cli::{Cli, Command};
+use modules::config::{ElevationPolicy, ManualOverride, Settings};
+use modules::discord::DiscordClient;
+use modules::discord_actor;
+use modules::hooks;
+use modules::assets;
+use modules::control;
+use modules::mqtt;
+use modules::obs;
+use modules::trace::{self, TraceRecorder};
 use modules::ui::run_tui;
 use modules::utils::run_background_tasks;
+use modules::webhooks;
 use modules::window::{set_console_title, set_console_icon};
 use parking_lot::Mutex;
 use std::sync::atomic::AtomicBool;
@@ -10,15 +22,26 @@ use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // 0. Ensure Admin Privileges
-    if !modules::admin::is_elevated() {
-        // Attempt to restart as admin
-        if let Ok(_) = modules::admin::run_as_admin() {
-            return Ok(());
+    let cli = Cli::parse();
+    if let Some(Command::Ctl { cmd }) = cli.command.clone() {
+        return control::run_client(cmd).await;
+    }
+    if let Some(path) = &cli.replay {
+        return trace::run_replay(path);
+    }
+
+    // A second launch hands its arguments to the running instance instead of
+    // fighting it over the Discord presence
+    let endpoint = control::endpoint();
+    if let Ok(endpoint) = &endpoint {
+        if cli.takeover {
+            control::take_over(endpoint).await?;
+        } else if !cli.check_assets {
+            if let Some(message) = control::forward_launch(endpoint, &cli).await? {
+                println!("{}", message);
+                return Ok(());
+            }
         }
-        // If failed (user said no), we continue but warn.
-        // Actually, for a TUI, we might just want to print and exit, 
-        // but let's try to continue in case user doesn't need admin for their setup.
     }
 
     // 1. Setup Window Appearance (Title & Icon)
@@ -26,17 +49,125 @@ async fn main() -> anyhow::Result<()> {
     let _ = set_console_icon(); // Best effort
 
     // 2. Initialize State
-    let app_state = Arc::new(Mutex::new(AppState::new()));
+    let mut state = AppState::new();
+    match Settings::load() {
+        Ok(settings) => state.settings = settings,
+        Err(e) => state.add_log(
+            LogLevel::Error,
+            format!("Using default settings; the config file won't be saved over until it loads: {:#}", e),
+        ),
+    }
+    match ManualOverride::load() {
+        Ok(manual) => state.manual_override = manual,
+        Err(e) => state.add_log(LogLevel::Error, format!("Ignoring saved override: {:#}", e)),
+    }
+    if cli.check_assets {
+        return check_assets(&state.settings);
+    }
+
+    // Elevation, as the policy asks; --elevation applies to this run only
+    state.elevation_override = cli.elevation;
+    if cfg!(windows) {
+        let policy = state.elevation();
+        if modules::admin::is_elevated() {
+            state.add_log(LogLevel::Info, "Running as administrator".to_string());
+        } else {
+            match policy {
+                ElevationPolicy::Never => state.add_log(
+                    LogLevel::Info,
+                    "Elevation 'never': running without administrator rights".to_string(),
+                ),
+                ElevationPolicy::IfNeeded => state.add_log(
+                    LogLevel::Info,
+                    "Elevation 'if-needed': running without administrator rights; will ask only if RPCS3's window titles can't be read"
+                        .to_string(),
+                ),
+                ElevationPolicy::Always => match modules::admin::run_as_admin(&[]) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => state.add_log(
+                        LogLevel::Warning,
+                        format!("Elevation 'always': relaunch as administrator failed or was declined ({}); continuing without", e),
+                    ),
+                },
+            }
+        }
+    }
+
+    if cli.clear_override {
+        state.set_override(None);
+    } else if let Some(details) = &cli.override_details {
+        match ManualOverride::new(details, cli.override_state.as_deref(), cli.override_timer) {
+            Ok(manual) => state.set_override(Some(manual)),
+            Err(e) => anyhow::bail!("Invalid --override-details/--override-state: {}", e),
+        }
+    }
+    state.set_paused(cli.paused);
+    let recorder = match &cli.record {
+        Some(path) => {
+            let recorder = TraceRecorder::create(path)?;
+            state.add_log(LogLevel::Info, format!("Recording detection trace to {}", recorder.path));
+            Some(recorder)
+        }
+        None => None,
+    };
+    let startup_settings = state.settings.clone();
+    let mut discord_client = DiscordClient::new(&state.settings.client_id);
+    discord_client.set_preferred_endpoint(Some(state.settings.preferred_endpoint.as_str()).filter(|p| !p.is_empty()));
+    let app_state = Arc::new(Mutex::new(state));
     let running = Arc::new(AtomicBool::new(true));
 
+    // Holding the control endpoint is the single-instance lock
+    let _control = match endpoint.and_then(|e| control::start(&e, app_state.clone(), running.clone())) {
+        Ok(server) => {
+            app_state.lock().add_log(LogLevel::Info, format!("Control socket listening on {}", server.endpoint));
+            server
+        }
+        Err(e) if e.is::<control::InUse>() => {
+            anyhow::bail!("MotorStormRPC is already running ({}); exiting", e)
+        }
+        // Without the endpoint nothing stops a second instance, so don't run unlocked
+        Err(e) => anyhow::bail!("Can't set up the control socket that keeps a single instance running: {:#}", e),
+    };
+
+    assets::spawn_check(app_state.clone(), startup_settings);
+
+    // 3. Spawn Discord Actor (owns the IPC connection)
+    let (discord, discord_task) = discord_actor::spawn(discord_client, app_state.clone());
+
     // 4. Spawn Background Task (Logic)
     let state_clone = app_state.clone();
+    let discord_clone = discord.clone();
     tokio::spawn(async move {
-        run_background_tasks(state_clone).await;
+        run_background_tasks(state_clone, discord_clone, recorder).await;
     });
-
+    tokio::spawn(obs::run_obs_output(app_state.clone()));
+    tokio::spawn(webhooks::run_webhooks(app_state.clone()));
+    tokio::spawn(mqtt::run_mqtt(app_state.clone()));
+    tokio::spawn(hooks::run_hooks(app_state.clone()));
     // 5. Run TUI on Main Thread
-    let res = run_tui(app_state, running.clone());
-    
-    res
+    let result = run_tui(app_state, running.clone());
+
+    // 6. Clear the activity before exiting
+    discord_actor::shutdown(&discord, discord_task).await;
+    result
+}
+
+/// `--check-assets`: prints which image keys the application is missing.
+fn check_assets(settings: &Settings) -> anyhow::Result<()> {
+    let report = assets::check_assets(settings)?;
+    println!("Assets of application {} ({})", settings.client_id, report.url);
+    for key in &report.found {
+        println!("  found    {}", key);
+    }
+    for key in &report.missing {
+        println!("  MISSING  {}", key);
+    }
+    if !report.is_complete() {
+        anyhow::bail!(
+            "{} asset key(s) missing; upload them in the Discord Developer Portal under Rich Presence > Art Assets",
+            report.missing.len()
+        );
+    }
+    println!("All presence assets are present.");
+    Ok(())
 }
@@ -1,4 +1,6 @@
+use crate::modules::config::ELEVATION_GRACE_MS;
 use std::io::Error;
+#[cfg(windows)]
 use std::ptr::null_mut;
 
 #[cfg(windows)]
@@ -38,7 +40,38 @@ pub fn is_elevated() -> bool {
     true
 }
 
-pub fn run_as_admin() -> Result<(), Error> {
+/// String form of the current user's SID, e.g. `S-1-5-21-...`; the same
+/// whether or not the process is elevated.
+#[cfg(windows)]
+pub fn current_user_sid() -> Result<String, Error> {
+    use windows::core::PWSTR;
+    use windows::Win32::Foundation::{CloseHandle, LocalFree, HLOCAL};
+    use windows::Win32::Security::Authorization::ConvertSidToStringSidW;
+    use windows::Win32::Security::{TokenUser, TOKEN_USER};
+
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token)?;
+        // TOKEN_USER is followed by the SID it points to, so ask for the size first
+        let mut size = 0;
+        let _ = GetTokenInformation(token, TokenUser, None, 0, &mut size);
+        let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+        let result = GetTokenInformation(token, TokenUser, Some(buffer.as_mut_ptr().cast()), size, &mut size);
+        let _ = CloseHandle(token);
+        result?;
+
+        let user = &*(buffer.as_ptr() as *const TOKEN_USER);
+        let mut sid = PWSTR::null();
+        ConvertSidToStringSidW(user.User.Sid, &mut sid)?;
+        let text = sid.to_string();
+        let _ = LocalFree(HLOCAL(sid.0.cast()));
+        text.map_err(Error::other)
+    }
+}
+
+/// Relaunches this executable elevated with the current arguments plus `extra_args`.
+#[cfg_attr(not(windows), allow(unused_variables))]
+pub fn run_as_admin(extra_args: &[&str]) -> Result<(), Error> {
     #[cfg(windows)]
     unsafe {
         use std::ffi::OsStr;
@@ -50,6 +83,18 @@ pub fn run_as_admin() -> Result<(), Error> {
             .encode_wide()
             .chain(std::iter::once(0))
             .collect();
+
+        // Forward our command line so flags survive the elevated relaunch
+        let params = env::args()
+            .skip(1)
+            .chain(extra_args.iter().map(|arg| arg.to_string()))
+            .map(|arg| quote_arg(&arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let params_wide: Vec<u16> = OsStr::new(&params)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
         
         // Operation "runas" requests elevation
         let operation = "runas\0".encode_utf16().collect::<Vec<u16>>();
@@ -58,7 +103,7 @@ pub fn run_as_admin() -> Result<(), Error> {
             HWND(0),
             PCWSTR(operation.as_ptr()),
             PCWSTR(exe_path_wide.as_ptr()),
-            PCWSTR(null_mut()),
+            PCWSTR(params_wide.as_ptr()),
             PCWSTR(null_mut()),
             SW_SHOW,
         );
@@ -69,3 +114,93 @@ pub fn run_as_admin() -> Result<(), Error> {
     }
     Ok(())
 }
+
+/// Quotes `arg` so `CommandLineToArgvW` splits it back out unchanged: backslashes
+/// are doubled before an embedded quote (which is escaped) and before the closing quote.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn quote_arg(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Decides when `elevation = if-needed` should ask for administrator rights:
+/// once the same emulator has refused access to its windows for `ELEVATION_GRACE_MS`,
+/// so one that is still opening its window doesn't trigger a prompt.
+#[derive(Default)]
+pub struct ElevationWatch {
+    unreadable_since: Option<(u32, i64)>,
+    asked: bool,
+}
+
+impl ElevationWatch {
+    /// Feeds the latest scan's unreadable emulator, if any. Returns its pid when it is
+    /// time to ask; that happens at most once per run.
+    pub fn observe(&mut self, unreadable_pid: Option<u32>, now_ms: i64) -> Option<u32> {
+        if self.asked {
+            return None;
+        }
+        match (unreadable_pid, self.unreadable_since) {
+            (None, _) => {
+                self.unreadable_since = None;
+                None
+            }
+            (Some(pid), Some((since_pid, since))) if pid == since_pid => {
+                self.asked = now_ms - since >= ELEVATION_GRACE_MS;
+                self.asked.then_some(pid)
+            }
+            (Some(pid), _) => {
+                self.unreadable_since = Some((pid, now_ms));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_arguments_for_the_windows_command_line() {
+        assert_eq!(quote_arg("--paused"), r#""--paused""#);
+        assert_eq!(quote_arg(""), r#""""#);
+        assert_eq!(quote_arg("Lap Times"), r#""Lap Times""#);
+        assert_eq!(quote_arg(r"a\b"), r#""a\b""#);
+        // Trailing backslashes would otherwise escape the closing quote
+        assert_eq!(quote_arg(r"C:\traces\"), r#""C:\traces\\""#);
+        assert_eq!(quote_arg(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_arg(r#"a\"b"#), r#""a\\\"b""#);
+    }
+
+    #[test]
+    fn asks_once_after_the_grace_period() {
+        let mut watch = ElevationWatch::default();
+        assert_eq!(watch.observe(Some(42), 0), None);
+        assert_eq!(watch.observe(Some(42), ELEVATION_GRACE_MS - 1), None);
+        // A readable window or another emulator restarts the wait
+        assert_eq!(watch.observe(None, ELEVATION_GRACE_MS), None);
+        assert_eq!(watch.observe(Some(42), ELEVATION_GRACE_MS + 1), None);
+        assert_eq!(watch.observe(Some(7), 2 * ELEVATION_GRACE_MS + 1), None);
+        assert_eq!(watch.observe(Some(7), 3 * ELEVATION_GRACE_MS + 1), Some(7));
+        assert_eq!(watch.observe(Some(7), 4 * ELEVATION_GRACE_MS), None);
+    }
+}
@@ -0,0 +1,503 @@
+//! Optional local HTTP API for stream widgets and bots.
+//!
+//! Read-only views of `AppState` as JSON, plus a few endpoints that act like
+//! the TUI keys (manual override, pause), and a WebSocket at `/events` that
+//! pushes `events::AppEvent`s, and optionally Prometheus `/metrics`. Only
+//! ever bound to 127.0.0.1.
+//!
+//! Binding to loopback doesn't keep web pages out: a page can reach the port
+//! through DNS rebinding or a plain cross-site form post. Requests must name
+//! the local address in `Host`, come from a local or configured `Origin` if
+//! they carry one (mutating requests only from a local one), and mutating
+//! requests must be JSON, which browsers won't send cross-site without a
+//! preflight this server never answers. The `/events` upgrade goes through
+//! the same checks.
+
+use crate::modules::app::AppState;
+use crate::modules::config::ManualOverride;
+use crate::modules::metrics;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+type SharedState = Arc<Mutex<AppState>>;
+
+/// A running API server; stops when dropped.
+pub struct ApiServer {
+    pub port: u16,
+    task: JoinHandle<()>,
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Binds `127.0.0.1:port` and serves the API in the background.
+/// Must be called from within the Tokio runtime.
+pub fn start(app_state: SharedState, port: u16) -> std::io::Result<ApiServer> {
+    let listener = std::net::TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let port = listener.local_addr()?.port();
+    let app = router(app_state, port);
+    let task = tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    Ok(ApiServer { port, task })
+}
+
+/// Routes of the API served on `127.0.0.1:port`; `port` is checked against `Host`.
+pub fn router(app_state: SharedState, port: u16) -> Router {
+    Router::new()
+        .route("/status", get(status))
+        .route("/sessions", get(sessions))
+        .route("/logs", get(logs))
+        .route("/override", post(set_override).delete(clear_override))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/events", get(events))
+        .route("/metrics", get(prometheus))
+        .layer(middleware::from_fn_with_state((app_state.clone(), port), guard))
+        .with_state(app_state)
+}
+
+fn forbidden(status: StatusCode, error: &str) -> Response {
+    (status, Json(json!({ "error": error }))).into_response()
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Whether `host` (a `Host` header, or an origin without its scheme) names this server.
+fn is_local_host(host: &str, port: u16) -> bool {
+    host == format!("127.0.0.1:{}", port) || host == format!("localhost:{}", port)
+}
+
+fn is_local_origin(origin: &str, port: u16) -> bool {
+    origin.strip_prefix("http://").is_some_and(|host| is_local_host(host, port))
+}
+
+/// Rejects requests that could come from a web page rather than a local tool
+/// or a widget from `api_allowed_origins`.
+async fn guard(State((app_state, port)): State<(SharedState, u16)>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    if !header_str(headers, header::HOST).is_some_and(|host| is_local_host(host, port)) {
+        return forbidden(StatusCode::FORBIDDEN, "Host must be 127.0.0.1 or localhost");
+    }
+    let mutating = matches!(*request.method(), Method::POST | Method::DELETE);
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        let allowed = origin.to_str().is_ok_and(|origin| {
+            is_local_origin(origin, port)
+                || (!mutating && app_state.lock().settings.api_allowed_origins.iter().any(|o| o.trim_end_matches('/') == origin))
+        });
+        if !allowed {
+            return forbidden(StatusCode::FORBIDDEN, "Cross-origin requests are not allowed");
+        }
+    }
+    if mutating {
+        let json = header_str(headers, header::CONTENT_TYPE)
+            .and_then(|v| v.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+        if !json {
+            return forbidden(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Content-Type must be application/json");
+        }
+    }
+    next.run(request).await
+}
+
+/// Current game, session and Discord state, as served by `/status`.
+pub fn status_json(state: &AppState) -> Value {
+    let now = Utc::now().timestamp();
+    let discord = &state.discord;
+    json!({
+        "game_running": state.game_running,
+        "window_title": state.matched_window,
+        "session_start": state.start_timestamp,
+        "session_elapsed_secs": state.start_timestamp.map(|start| now - start),
+        "presence_paused": state.presence_paused,
+        "manual_override": state.manual_override.as_ref().map(|o| json!({
+            "details": o.details,
+            "state": o.state,
+            "timer_start": o.timer_start,
+        })),
+        "discord": {
+            "connected": discord.connected,
+            "user": discord.user.as_ref().map(|u| json!({ "id": u.id, "name": u.display_name() })),
+            "endpoint": discord.endpoint.as_ref().map(|e| e.path.clone()),
+            "client": discord.flavor.map(|f| f.to_string()),
+            "last_activity": discord.last_activity.as_ref().map(|sent| json!({
+                "activity": sent.activity,
+                "sent_at": sent.sent_at,
+            })),
+            "last_error": discord.last_error.as_ref().map(|e| json!({ "message": e.message, "at": e.at })),
+        },
+    })
+}
+
+async fn status(State(app_state): State<SharedState>) -> Json<Value> {
+    Json(status_json(&app_state.lock()))
+}
+
+async fn sessions(State(app_state): State<SharedState>) -> Json<Value> {
+    let state = app_state.lock();
+    let sessions: Vec<_> = state
+        .sessions
+        .iter()
+        .map(|s| json!({
+            "window_title": s.window,
+            "start": s.start_timestamp,
+            "end": s.end_timestamp,
+            "duration_secs": s.duration_secs(),
+        }))
+        .collect();
+    Json(Value::Array(sessions))
+}
+
+#[derive(Deserialize)]
+struct LogQuery {
+    /// Only return entries with a sequence number above this.
+    since: Option<u64>,
+}
+
+async fn logs(State(app_state): State<SharedState>, Query(query): Query<LogQuery>) -> Json<Value> {
+    let state = app_state.lock();
+    let logs: Vec<_> = state
+        .logs
+        .iter()
+        .filter(|l| query.since.is_none_or(|since| l.seq > since))
+        .map(|l| json!({ "seq": l.seq, "level": l.level.name(), "message": l.message }))
+        .collect();
+    Json(Value::Array(logs))
+}
+
+#[derive(Deserialize)]
+struct OverrideRequest {
+    details: String,
+    state: Option<String>,
+    #[serde(default)]
+    timer: bool,
+}
+
+async fn set_override(State(app_state): State<SharedState>, Json(request): Json<OverrideRequest>) -> Response {
+    match ManualOverride::new(&request.details, request.state.as_deref(), request.timer) {
+        Ok(manual) => {
+            let mut state = app_state.lock();
+            state.set_override(Some(manual));
+            Json(status_json(&state)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+async fn clear_override(State(app_state): State<SharedState>) -> Json<Value> {
+    let mut state = app_state.lock();
+    state.set_override(None);
+    Json(status_json(&state))
+}
+
+async fn pause(State(app_state): State<SharedState>) -> Json<Value> {
+    let mut state = app_state.lock();
+    state.set_paused(true);
+    Json(status_json(&state))
+}
+
+async fn resume(State(app_state): State<SharedState>) -> Json<Value> {
+    let mut state = app_state.lock();
+    state.set_paused(false);
+    Json(status_json(&state))
+}
+
+async fn prometheus(State(app_state): State<SharedState>) -> Response {
+    let state = app_state.lock();
+    if !state.settings.metrics_enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render(&state)).into_response()
+}
+
+async fn events(State(app_state): State<SharedState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| stream_events(socket, app_state))
+}
+
+/// First message on `/events`, so clients start from the current state.
+fn snapshot_json(state: &AppState) -> Value {
+    json!({ "event": "snapshot", "at": Utc::now().timestamp(), "status": status_json(state) })
+}
+
+async fn stream_events(mut socket: WebSocket, app_state: SharedState) {
+    // Subscribe before taking the snapshot so no event falls in between
+    let (mut events, snapshot) = {
+        let state = app_state.lock();
+        (state.events.subscribe(), snapshot_json(&state))
+    };
+    if send_json(&mut socket, &snapshot).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let message = match event {
+                    Ok(event) => serde_json::to_value(&event).unwrap_or_default(),
+                    // Too slow to keep up: start over from the current state
+                    Err(RecvError::Lagged(_)) => snapshot_json(&app_state.lock()),
+                    Err(RecvError::Closed) => return,
+                };
+                if send_json(&mut socket, &message).await.is_err() {
+                    return;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send_json(socket: &mut WebSocket, value: &Value) -> Result<(), axum::Error> {
+    socket.send(Message::Text(value.to_string().into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::app::{LogLevel, SessionRecord};
+
+    fn serve(state: AppState) -> (SharedState, ApiServer) {
+        let app_state = Arc::new(Mutex::new(state));
+        let server = start(app_state.clone(), 0).unwrap();
+        (app_state, server)
+    }
+
+    // ureq is blocking; keep it off the runtime threads
+    async fn request(method: &'static str, url: String, body: Option<Value>) -> (u16, Value) {
+        let headers: &[(&str, &str)] = match method {
+            "GET" => &[],
+            _ => &[("Content-Type", "application/json")],
+        };
+        request_with(method, url, headers, body).await
+    }
+
+    async fn request_with(method: &'static str, url: String, headers: &[(&str, &str)], body: Option<Value>) -> (u16, Value) {
+        let headers: Vec<(String, String)> = headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        tokio::task::spawn_blocking(move || {
+            let request = headers
+                .into_iter()
+                .fold(ureq::request(method, &url), |request, (name, value)| request.set(&name, &value));
+            let result = match body {
+                Some(body) => request.send_json(body),
+                None => request.call(),
+            };
+            let response = match result {
+                Ok(response) => response,
+                Err(ureq::Error::Status(_, response)) => response,
+                Err(e) => panic!("request failed: {}", e),
+            };
+            (response.status(), response.into_json().unwrap())
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn status_reports_game_and_discord_state() {
+        let mut state = AppState::new();
+        state.game_running = true;
+        state.matched_window = Some("MotorStorm: Pacific Rift".to_string());
+        state.start_timestamp = Some(Utc::now().timestamp() - 90);
+        let (_, server) = serve(state);
+
+        let (code, body) = request("GET", format!("http://127.0.0.1:{}/status", server.port), None).await;
+        assert_eq!(code, 200);
+        assert_eq!(body["game_running"], true);
+        assert_eq!(body["window_title"], "MotorStorm: Pacific Rift");
+        assert!(body["session_elapsed_secs"].as_i64().unwrap() >= 90);
+        assert_eq!(body["discord"]["connected"], false);
+    }
+
+    #[tokio::test]
+    async fn sessions_and_logs_are_listed() {
+        let mut state = AppState::new();
+        state.record_session(SessionRecord { window: None, start_timestamp: 100, end_timestamp: 400 });
+        state.add_log(LogLevel::Info, "first".to_string());
+        state.add_log(LogLevel::Game, "second".to_string());
+        let (_, server) = serve(state);
+        let base = format!("http://127.0.0.1:{}", server.port);
+
+        let (_, sessions) = request("GET", format!("{}/sessions", base), None).await;
+        assert_eq!(sessions[0]["duration_secs"], 300);
+
+        let (_, logs) = request("GET", format!("{}/logs?since=0", base), None).await;
+        assert_eq!(logs.as_array().unwrap().len(), 1);
+        assert_eq!(logs[0]["level"], "game");
+        assert_eq!(logs[0]["message"], "second");
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_toggle_privacy_mode() {
+        let (app_state, server) = serve(AppState::new());
+        let base = format!("http://127.0.0.1:{}", server.port);
+
+        let (_, body) = request("POST", format!("{}/pause", base), None).await;
+        assert_eq!(body["presence_paused"], true);
+        assert!(app_state.lock().presence_paused);
+
+        request("POST", format!("{}/resume", base), None).await;
+        assert!(!app_state.lock().presence_paused);
+    }
+
+    #[tokio::test]
+    async fn invalid_override_is_rejected() {
+        let (app_state, server) = serve(AppState::new());
+
+        let url = format!("http://127.0.0.1:{}/override", server.port);
+        let (code, body) = request("POST", url, Some(json!({ "details": "x" }))).await;
+        assert_eq!(code, 400);
+        assert!(body["error"].as_str().unwrap().contains("2-128"));
+        assert!(app_state.lock().manual_override.is_none());
+    }
+
+    #[tokio::test]
+    async fn foreign_host_is_rejected() {
+        let (_, server) = serve(AppState::new());
+        let url = format!("http://127.0.0.1:{}/status", server.port);
+
+        let (code, _) = request_with("GET", url.clone(), &[("Host", "attacker.example")], None).await;
+        assert_eq!(code, 403);
+        let (code, _) = request_with("GET", url, &[("Host", "localhost")], None).await;
+        assert_eq!(code, 403);
+    }
+
+    #[tokio::test]
+    async fn foreign_origin_is_rejected() {
+        let (app_state, server) = serve(AppState::new());
+        let base = format!("http://127.0.0.1:{}", server.port);
+        let json = ("Content-Type", "application/json");
+
+        let (code, _) = request_with("GET", format!("{}/status", base), &[("Origin", "https://evil.example")], None).await;
+        assert_eq!(code, 403);
+        let (code, _) = request_with("POST", format!("{}/pause", base), &[json, ("Origin", "null")], None).await;
+        assert_eq!(code, 403);
+        assert!(!app_state.lock().presence_paused);
+
+        let (code, _) = request_with("POST", format!("{}/pause", base), &[json, ("Origin", &base)], None).await;
+        assert_eq!(code, 200);
+        assert!(app_state.lock().presence_paused);
+    }
+
+    #[tokio::test]
+    async fn event_stream_checks_origin() {
+        use tungstenite::client::IntoClientRequest;
+        use tungstenite::http::HeaderValue;
+
+        let (app_state, server) = serve(AppState::new());
+        app_state.lock().settings.api_allowed_origins = vec!["https://widgets.example/".to_string()];
+        let url = format!("ws://127.0.0.1:{}/events", server.port);
+        let connect = |origin: &'static str| {
+            let mut request = url.clone().into_client_request().unwrap();
+            request.headers_mut().insert("Origin", HeaderValue::from_static(origin));
+            tokio::task::spawn_blocking(move || match tungstenite::connect(request) {
+                Ok((_, response)) => response.status().as_u16(),
+                Err(tungstenite::Error::Http(response)) => response.status().as_u16(),
+                Err(e) => panic!("upgrade failed: {}", e),
+            })
+        };
+
+        assert_eq!(connect("https://evil.example").await.unwrap(), 403);
+        assert_eq!(connect("https://widgets.example").await.unwrap(), 101);
+
+        // Configured origins may only read
+        let base = format!("http://127.0.0.1:{}", server.port);
+        let headers = [("Content-Type", "application/json"), ("Origin", "https://widgets.example")];
+        let (code, _) = request_with("POST", format!("{}/pause", base), &headers, None).await;
+        assert_eq!(code, 403);
+    }
+
+    #[tokio::test]
+    async fn mutating_requests_must_be_json() {
+        let mut state = AppState::new();
+        state.presence_paused = true;
+        state.set_override(Some(ManualOverride::new("Time trial", None, false).unwrap()));
+        let (app_state, server) = serve(state);
+        let base = format!("http://127.0.0.1:{}", server.port);
+        let form = [("Content-Type", "application/x-www-form-urlencoded")];
+
+        for (method, path) in [("POST", "/pause"), ("POST", "/resume"), ("POST", "/override"), ("DELETE", "/override")] {
+            let (code, _) = request_with(method, format!("{}{}", base, path), &form, None).await;
+            assert_eq!(code, 415, "{} {}", method, path);
+            let (code, _) = request_with(method, format!("{}{}", base, path), &[], None).await;
+            assert_eq!(code, 415, "{} {} without Content-Type", method, path);
+        }
+        let state = app_state.lock();
+        assert!(state.presence_paused);
+        assert!(state.manual_override.is_some());
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_only_when_enabled() {
+        let (app_state, server) = serve(AppState::new());
+        let url = format!("http://127.0.0.1:{}/metrics", server.port);
+
+        let fetch = |url: String| tokio::task::spawn_blocking(move || match ureq::get(&url).call() {
+            Ok(response) => (response.status(), response.into_string().unwrap()),
+            Err(ureq::Error::Status(code, _)) => (code, String::new()),
+            Err(e) => panic!("request failed: {}", e),
+        });
+        assert_eq!(fetch(url.clone()).await.unwrap().0, 404);
+
+        app_state.lock().settings.metrics_enabled = true;
+        let (code, body) = fetch(url).await.unwrap();
+        assert_eq!(code, 200);
+        assert!(body.contains("motorstorm_game_running 0\n"));
+    }
+
+    #[tokio::test]
+    async fn event_stream_replays_state_then_pushes_events() {
+        let mut state = AppState::new();
+        state.game_running = true;
+        let (app_state, server) = serve(state);
+        let url = format!("ws://127.0.0.1:{}/events", server.port);
+
+        let (mut socket, _) = tokio::task::spawn_blocking(move || tungstenite::connect(url).unwrap())
+            .await
+            .unwrap();
+        let read = |socket: &mut tungstenite::WebSocket<_>| -> Value {
+            serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap()
+        };
+
+        let snapshot = read(&mut socket);
+        assert_eq!(snapshot["event"], "snapshot");
+        assert_eq!(snapshot["status"]["game_running"], true);
+
+        app_state.lock().set_paused(true);
+        let (mut socket, paused, log) = tokio::task::spawn_blocking(move || {
+            let paused = read(&mut socket);
+            let log = read(&mut socket);
+            (socket, paused, log)
+        })
+        .await
+        .unwrap();
+        assert_eq!(paused["event"], "presence_paused");
+        assert!(paused["at"].as_i64().is_some());
+        assert_eq!(log["event"], "log");
+        assert_eq!(log["message"], "Rich Presence paused (privacy mode)");
+        socket.close(None).unwrap();
+    }
+}
@@ -1,13 +1,18 @@
-use crate::modules::config::LOG_CAPACITY;
+use crate::modules::config::{ElevationPolicy, ManualOverride, Settings, LOG_CAPACITY, SESSION_HISTORY_CAPACITY};
+use crate::modules::discord_actor::DiscordStatus;
+use crate::modules::events::{EventBus, EventKind};
+use crate::modules::metrics::Metrics;
 use std::collections::VecDeque;
 
 #[derive(Clone)]
 pub struct LogMessage {
+    /// Monotonic sequence number, stable across `LOG_CAPACITY` evictions.
+    pub seq: u64,
     pub message: String,
     pub level: LogLevel,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum LogLevel {
     Info,
     Success,
@@ -17,28 +22,79 @@ pub enum LogLevel {
     Game,
 }
 
+impl LogLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Info => "info",
+            LogLevel::Success => "success",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+            LogLevel::Game => "game",
+        }
+    }
+}
+
+/// A finished play session.
+#[derive(Clone, Debug)]
+pub struct SessionRecord {
+    pub window: Option<String>,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+}
+
+impl SessionRecord {
+    pub fn duration_secs(&self) -> i64 {
+        self.end_timestamp - self.start_timestamp
+    }
+}
+
 pub struct AppState {
     pub game_running: bool,
-    pub discord_connected: bool,
+    /// Latest status published by the Discord actor.
+    pub discord: DiscordStatus,
     pub debug_mode: bool,
     pub logs: VecDeque<LogMessage>,
+    pub next_log_seq: u64,
     pub start_timestamp: Option<i64>,
     pub matched_window: Option<String>,
+    /// Emulator process the game window belongs to.
+    pub game_pid: Option<u32>,
     pub cpu_usage: f32,
     pub ram_usage: u64,
+    /// Active settings; the background task picks up changes on its next iteration.
+    pub settings: Settings,
+    /// `--elevation` for this run; kept apart so saved or reloaded settings don't drop it.
+    pub elevation_override: Option<ElevationPolicy>,
+    /// Pinned activity shown instead of the detected game, if any.
+    pub manual_override: Option<ManualOverride>,
+    /// Privacy mode: detection continues but nothing is broadcast to Discord.
+    pub presence_paused: bool,
+    pub sessions: VecDeque<SessionRecord>,
+    /// Published to WebSocket clients and other integrations.
+    pub events: EventBus,
+    pub metrics: Metrics,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             game_running: false,
-            discord_connected: false,
+            discord: DiscordStatus::default(),
             debug_mode: false,
             logs: VecDeque::with_capacity(LOG_CAPACITY),
+            next_log_seq: 0,
             start_timestamp: None,
             matched_window: None,
+            game_pid: None,
             cpu_usage: 0.0,
             ram_usage: 0,
+            settings: Settings::default(),
+            elevation_override: None,
+            manual_override: None,
+            presence_paused: false,
+            sessions: VecDeque::new(),
+            events: EventBus::new(),
+            metrics: Metrics::default(),
         }
     }
 
@@ -46,6 +102,52 @@ impl AppState {
         if self.logs.len() >= LOG_CAPACITY {
             self.logs.pop_front();
         }
-        self.logs.push_back(LogMessage { message: msg, level });
+        self.events.publish(EventKind::Log { seq: self.next_log_seq, level: level.name(), message: msg.clone() });
+        self.logs.push_back(LogMessage { seq: self.next_log_seq, message: msg, level });
+        self.next_log_seq += 1;
+    }
+
+    /// Sets or clears the manual override and persists it for the next launch.
+    pub fn set_override(&mut self, value: Option<ManualOverride>) {
+        if let Err(e) = ManualOverride::store(value.as_ref()) {
+            self.add_log(LogLevel::Error, format!("Failed to persist override: {:#}", e));
+        }
+        match &value {
+            Some(o) => {
+                let state = o.state.as_deref().map(|s| format!(" / {}", s)).unwrap_or_default();
+                self.add_log(LogLevel::Info, format!("Manual presence set: {}{}", o.details, state));
+            }
+            None if self.manual_override.is_some() => {
+                self.add_log(LogLevel::Info, "Manual presence cleared, resuming detection".to_string());
+            }
+            None => {}
+        }
+        self.manual_override = value;
+    }
+
+    /// The elevation policy in effect: `--elevation` if given, else the setting.
+    pub fn elevation(&self) -> ElevationPolicy {
+        self.elevation_override.unwrap_or(self.settings.elevation)
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.presence_paused == paused {
+            return;
+        }
+        self.presence_paused = paused;
+        self.events.publish(if paused { EventKind::PresencePaused } else { EventKind::PresenceResumed });
+        if paused {
+            self.add_log(LogLevel::Warning, "Rich Presence paused (privacy mode)".to_string());
+        } else {
+            self.add_log(LogLevel::Success, "Rich Presence resumed".to_string());
+        }
+    }
+
+    pub fn record_session(&mut self, record: SessionRecord) {
+        self.metrics.add_session(record.window.as_deref(), record.duration_secs());
+        if self.sessions.len() >= SESSION_HISTORY_CAPACITY {
+            self.sessions.pop_front();
+        }
+        self.sessions.push_back(record);
     }
 }
@@ -0,0 +1,115 @@
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::{Settings, ASSET_CHECK_TIMEOUT_MS, ASSET_KEYS};
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct AssetEntry {
+    name: String,
+}
+
+/// Outcome of comparing the presence's image keys with the application's uploaded assets.
+#[derive(Debug, PartialEq)]
+pub struct AssetReport {
+    pub url: String,
+    pub found: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl AssetReport {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+pub fn assets_url(settings: &Settings) -> String {
+    settings.assets_url.replace("{client_id}", &settings.client_id)
+}
+
+/// Downloads the application's asset list and reports which referenced keys are missing.
+/// Discord matches asset keys case-insensitively.
+pub fn check_assets(settings: &Settings) -> Result<AssetReport> {
+    let url = assets_url(settings);
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_millis(ASSET_CHECK_TIMEOUT_MS))
+        .build();
+    let response = match agent.get(&url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => {
+            bail!("No application {} found at {} (check the client ID)", settings.client_id, url)
+        }
+        Err(ureq::Error::Status(code, _)) => bail!("{} answered HTTP {}", url, code),
+        Err(e) => return Err(e).context("Failed to fetch the asset list"),
+    };
+    let entries: Vec<AssetEntry> = response
+        .into_json()
+        .with_context(|| format!("Unexpected asset list from {}", url))?;
+
+    let (found, missing) = ASSET_KEYS
+        .iter()
+        .map(|key| key.to_string())
+        .partition(|key| entries.iter().any(|e| e.name.eq_ignore_ascii_case(key)));
+    Ok(AssetReport { url, found, missing })
+}
+
+/// Runs the asset check off the async runtime and writes the outcome to the log.
+pub fn spawn_check(app_state: Arc<Mutex<AppState>>, settings: Settings) {
+    tokio::task::spawn_blocking(move || {
+        let result = check_assets(&settings);
+        let mut state = app_state.lock();
+        match result {
+            Ok(report) if report.is_complete() => {
+                state.add_log(LogLevel::Success, format!("All presence assets found ({})", report.found.join(", ")));
+            }
+            Ok(report) => state.add_log(LogLevel::Warning, format!(
+                "Missing Discord assets: {}. Upload them under Rich Presence > Art Assets or the images won't show",
+                report.missing.join(", ")
+            )),
+            Err(e) => state.add_log(LogLevel::Warning, format!("Could not check Discord assets: {:#}", e)),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::mock_http::MockHttp;
+
+    fn settings_for(mock: &MockHttp) -> Settings {
+        Settings {
+            assets_url: mock.url("/applications/{client_id}/assets"),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn reports_missing_keys() {
+        let mock = MockHttp::start(r#"[{"id": "1", "type": 1, "name": "MotorStorm"}, {"id": "2", "type": 1, "name": "logo"}]"#);
+        let settings = settings_for(&mock);
+
+        let report = check_assets(&settings).unwrap();
+        assert_eq!(report.found, vec!["motorstorm"]);
+        assert_eq!(report.missing, vec!["rpcs3"]);
+        assert!(!report.is_complete());
+        assert_eq!(mock.requests()[0].method, "GET");
+        assert_eq!(mock.requests()[0].path, format!("/applications/{}/assets", settings.client_id));
+    }
+
+    #[test]
+    fn complete_asset_list_passes() {
+        let mock = MockHttp::start(r#"[{"id": "1", "type": 1, "name": "motorstorm"}, {"id": "2", "type": 1, "name": "rpcs3"}]"#);
+        assert!(check_assets(&settings_for(&mock)).unwrap().is_complete());
+    }
+
+    #[test]
+    fn unknown_application_is_explained() {
+        let mock = MockHttp::start("[]");
+        mock.respond(404, r#"{"message": "Unknown Application", "code": 10002}"#);
+
+        let err = check_assets(&settings_for(&mock)).unwrap_err();
+        assert!(err.to_string().contains("check the client ID"));
+    }
+}
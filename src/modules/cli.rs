@@ -0,0 +1,90 @@
+use crate::modules::config::ElevationPolicy;
+use clap::{Parser, Subcommand};
+
+/// Discord Rich Presence for MotorStorm: Pacific Rift via RPCS3.
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about)]
+pub struct Cli {
+    /// Pin a manual activity that overrides automatic detection until cleared
+    #[arg(long, value_name = "TEXT")]
+    pub override_details: Option<String>,
+
+    /// Second line of the manual activity
+    #[arg(long, value_name = "TEXT", requires = "override_details")]
+    pub override_state: Option<String>,
+
+    /// Show an elapsed timer on the manual activity
+    #[arg(long, requires = "override_details")]
+    pub override_timer: bool,
+
+    /// Remove a previously pinned manual activity and resume automatic detection
+    #[arg(long, conflicts_with = "override_details")]
+    pub clear_override: bool,
+
+    /// Start with Rich Presence paused; detection and session recording keep running
+    #[arg(long)]
+    pub paused: bool,
+
+    /// Check that the application's Rich Presence assets exist, print the result and exit
+    #[arg(long)]
+    pub check_assets: bool,
+
+    /// Write every scan (processes, window titles, timings) to a trace file
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<String>,
+
+    /// Run a recorded trace through detection, print what happened and exit
+    #[arg(long, value_name = "FILE", conflicts_with = "record")]
+    pub replay: Option<String>,
+
+    /// When to run as administrator; overrides the `elevation` setting
+    #[arg(long, value_enum)]
+    pub elevation: Option<ElevationPolicy>,
+
+    /// Ask a running instance to quit and take its place (used by the elevated relaunch)
+    #[arg(long, hide = true)]
+    pub takeover: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Send a command to the running instance over its control socket
+    Ctl {
+        #[command(subcommand)]
+        cmd: CtlCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CtlCommand {
+    /// Print the current game, session and Discord state
+    Status,
+    /// Pause Rich Presence (privacy mode)
+    Pause,
+    /// Resume Rich Presence
+    Resume,
+    /// Pin a manual activity, or remove it with --clear
+    Override {
+        /// First line of the activity
+        #[arg(required_unless_present = "clear")]
+        details: Option<String>,
+        /// Second line of the activity
+        #[arg(long, value_name = "TEXT")]
+        state: Option<String>,
+        /// Show an elapsed timer
+        #[arg(long)]
+        timer: bool,
+        /// Remove the manual activity and resume automatic detection
+        #[arg(long, conflicts_with_all = ["details", "state", "timer"])]
+        clear: bool,
+    },
+    /// Re-read config.toml
+    ReloadConfig,
+    /// Bring the running instance's window to the front
+    Focus,
+    /// Exit the running instance
+    Quit,
+}
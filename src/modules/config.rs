@@ -1,3 +1,10 @@
+use crate::modules::webhooks::{render_body, sample_vars};
+use anyhow::{bail, Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
 pub const CLIENT_ID: &str = "1164946062499389470";
 pub const GAME_NAME: &str = "MotorStorm®: Pacific Rift";
 pub const PROCESS_CHECK_INTERVAL_MS: u64 = 3000;
@@ -17,4 +24,730 @@ pub const GAME_WINDOW_TITLES: &[&str] = &[
     "storm",
 ];
 
-pub const LOG_CAPACITY: usize = 50;
+pub const DETAILS_TEMPLATE: &str = "Playing {game}";
+pub const STATE_TEMPLATE: &str = "On RPCS3 Emulator";
+
+// Reconnect backoff: doubles from the base delay up to the cap, randomised by ±jitter
+pub const RECONNECT_BASE_MS: u64 = 2000;
+pub const RECONNECT_MAX_MS: u64 = 60000;
+pub const RECONNECT_JITTER: f64 = 0.2;
+
+// Discord actor: how often it checks for due reconnects, and how long exit waits for it to clear the activity
+pub const ACTOR_TICK_MS: u64 = 250;
+pub const SHUTDOWN_TIMEOUT_MS: u64 = 2000;
+
+// Discord accepts about 5 SET_ACTIVITY calls per 20 seconds
+pub const ACTIVITY_RATE_LIMIT: u32 = 5;
+pub const ACTIVITY_RATE_WINDOW_MS: u64 = 20000;
+
+// Image keys the presence references; they must exist in the application's Rich Presence assets
+pub const LARGE_IMAGE_KEY: &str = "motorstorm";
+pub const SMALL_IMAGE_KEY: &str = "rpcs3";
+pub const ASSET_KEYS: &[&str] = &[LARGE_IMAGE_KEY, SMALL_IMAGE_KEY];
+// Public list of an application's uploaded assets; `{client_id}` is substituted
+pub const ASSETS_URL: &str = "https://discord.com/api/v10/oauth2/applications/{client_id}/assets";
+pub const ASSET_CHECK_TIMEOUT_MS: u64 = 5000;
+
+// Optional local HTTP API, only ever bound to 127.0.0.1
+pub const API_PORT: u16 = 8787;
+
+// Events webhooks and hooks may subscribe to
+pub const NOTIFY_EVENTS: &[&str] = &[
+    "session_started",
+    "session_stopped",
+    "game_changed",
+    "presence_paused",
+    "presence_resumed",
+    "discord_connected",
+    "discord_disconnected",
+];
+
+// Webhooks: the default body and delivery retries
+pub const WEBHOOK_BODY_TEMPLATE: &str =
+    r#"{"event": "{event}", "game": "{game}", "user": "{user}", "duration": "{duration}", "duration_secs": {duration_secs}}"#;
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 4;
+pub const WEBHOOK_RETRY_BASE_MS: u64 = 1000;
+pub const WEBHOOK_TIMEOUT_MS: u64 = 5000;
+
+// Presence scripts are stopped after this long or this many operations, whichever comes first
+pub const SCRIPT_TIME_LIMIT_MS: u64 = 50;
+pub const SCRIPT_MAX_OPERATIONS: u64 = 1_000_000;
+
+// Hook commands are killed after this long unless they set their own timeout
+pub const HOOK_TIMEOUT_MS: u64 = 10000;
+
+// MQTT publishing; topics live under the prefix, Home Assistant discovery under its own prefix
+pub const MQTT_PORT: u16 = 1883;
+pub const MQTT_TOPIC_PREFIX: &str = "motorstormrpc";
+pub const MQTT_DISCOVERY_PREFIX: &str = "homeassistant";
+pub const MQTT_KEEP_ALIVE_SECS: u64 = 30;
+pub const MQTT_RETRY_MS: u64 = 5000;
+
+// `elevation = if-needed` asks for administrator rights once RPCS3 refuses access to its windows this long
+pub const ELEVATION_GRACE_MS: i64 = 15000;
+// How long an elevated relaunch waits for the instance it replaces to exit
+pub const TAKEOVER_TIMEOUT_MS: u64 = 10000;
+
+pub const LOG_CAPACITY: usize = 500;
+pub const SESSION_HISTORY_CAPACITY: usize = 100;
+
+// Minimum interval between repeated debug diagnostics of the same kind
+pub const DEBUG_LOG_INTERVAL_MS: u64 = 10000;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+const OVERRIDE_FILE_NAME: &str = "override.toml";
+
+fn config_file(name: &str) -> Option<PathBuf> {
+    ProjectDirs::from("", "ZoniBoy00", "MotorStormRPC").map(|dirs| dirs.config_dir().join(name))
+}
+
+/// Per-user location for sockets; the config directory where there is no runtime directory.
+#[cfg(unix)]
+pub fn runtime_file(name: &str) -> Option<PathBuf> {
+    ProjectDirs::from("", "ZoniBoy00", "MotorStormRPC")
+        .map(|dirs| dirs.runtime_dir().unwrap_or(dirs.config_dir()).join(name))
+}
+
+fn write_config_file(name: &str, contents: &str) -> Result<PathBuf> {
+    let path = config_file(name).context("No config directory available")?;
+    write_file(&path, contents)?;
+    Ok(path)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// User-editable settings, persisted as TOML in the platform config directory.
+/// Missing keys fall back to the defaults above.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub client_id: String,
+    pub poll_interval_ms: u64,
+    pub start_cooldown_ms: i64,
+    pub stop_cooldown_ms: i64,
+    pub keywords: Vec<String>,
+    /// Presence text templates; `{game}` and `{window}` are substituted.
+    pub details_template: String,
+    pub state_template: String,
+    /// Rhai script deciding the activity while a game runs; empty uses the templates.
+    pub presence_script: String,
+    /// IPC socket or pipe to use when several Discord clients are open; empty picks the first found.
+    pub preferred_endpoint: String,
+    /// At most this many activity updates are sent per `activity_rate_window_ms`;
+    /// the latest change is sent once the window allows it.
+    pub activity_rate_limit: u32,
+    pub activity_rate_window_ms: u64,
+    /// Where the asset check reads the application's asset list; `{client_id}` is substituted.
+    pub assets_url: String,
+    /// Serve the local HTTP API on `127.0.0.1:api_port`.
+    pub api_enabled: bool,
+    pub api_port: u16,
+    /// Web origins besides the API's own, e.g. `https://widgets.example`, that
+    /// may read the API and open `/events`; edited in the config file only.
+    pub api_allowed_origins: Vec<String>,
+    /// Also serve Prometheus metrics at `/metrics` on the HTTP API.
+    pub metrics_enabled: bool,
+    /// Directory for OBS text/JSON outputs; empty disables them.
+    pub obs_output_dir: String,
+    /// HTTP endpoints notified about session events; edited in the config file only.
+    pub webhooks: Vec<WebhookTarget>,
+    /// Commands run on session events; edited in the config file only.
+    pub hooks: Vec<HookCommand>,
+    /// Publish game state to an MQTT broker.
+    pub mqtt_enabled: bool,
+    pub mqtt_host: String,
+    pub mqtt_port: u16,
+    /// Broker credentials; empty connects anonymously. Edited in the config file only.
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+    pub mqtt_topic_prefix: String,
+    /// Home Assistant discovery prefix; empty disables discovery.
+    pub mqtt_discovery_prefix: String,
+    /// When to run with administrator rights (Windows only).
+    pub elevation: ElevationPolicy,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            client_id: CLIENT_ID.to_string(),
+            poll_interval_ms: PROCESS_CHECK_INTERVAL_MS,
+            start_cooldown_ms: START_COOLDOWN_MS,
+            stop_cooldown_ms: STOP_COOLDOWN_MS,
+            keywords: GAME_WINDOW_TITLES.iter().map(|s| s.to_string()).collect(),
+            details_template: DETAILS_TEMPLATE.to_string(),
+            state_template: STATE_TEMPLATE.to_string(),
+            presence_script: String::new(),
+            preferred_endpoint: String::new(),
+            activity_rate_limit: ACTIVITY_RATE_LIMIT,
+            activity_rate_window_ms: ACTIVITY_RATE_WINDOW_MS,
+            assets_url: ASSETS_URL.to_string(),
+            api_enabled: false,
+            api_port: API_PORT,
+            api_allowed_origins: Vec::new(),
+            metrics_enabled: false,
+            obs_output_dir: String::new(),
+            webhooks: Vec::new(),
+            hooks: Vec::new(),
+            mqtt_enabled: false,
+            mqtt_host: "localhost".to_string(),
+            mqtt_port: MQTT_PORT,
+            mqtt_username: String::new(),
+            mqtt_password: String::new(),
+            mqtt_topic_prefix: MQTT_TOPIC_PREFIX.to_string(),
+            mqtt_discovery_prefix: MQTT_DISCOVERY_PREFIX.to_string(),
+            elevation: ElevationPolicy::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        config_file(CONFIG_FILE_NAME)
+    }
+
+    /// Loads settings from disk, returning defaults when no file exists yet.
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let settings: Self = toml::from_str(&text)
+            .with_context(|| format!("Invalid config in {}", path.display()))?;
+        settings.validate().map_err(anyhow::Error::msg)?;
+        Ok(settings)
+    }
+
+    /// Writes the settings to disk. Refuses while the file there fails to load:
+    /// the settings in memory are then defaults, and would replace everything in it.
+    pub fn save(&self) -> Result<PathBuf> {
+        let path = Self::path().context("No config directory available")?;
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    fn save_to(&self, path: &Path) -> Result<()> {
+        if let Err(e) = Self::load_from(path) {
+            bail!("{:#}; fix or remove it before saving", e);
+        }
+        write_file(path, &toml::to_string_pretty(self)?)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for field in SettingField::ALL {
+            field.validate(self)?;
+        }
+        for webhook in &self.webhooks {
+            webhook.validate()?;
+        }
+        for hook in &self.hooks {
+            hook.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Human-readable `label: old -> new` lines for every field that differs,
+    /// including those edited in the config file only. Secrets are never shown.
+    pub fn diff(&self, other: &Settings) -> Vec<String> {
+        let mut changes: Vec<String> = SettingField::ALL
+            .iter()
+            .filter_map(|field| {
+                let (old, new) = (field.get(self), field.get(other));
+                (old != new).then(|| format!("{}: {} -> {}", field.label(), old, new))
+            })
+            .collect();
+
+        let or_none = |value: String| if value.is_empty() { "(none)".to_string() } else { value };
+        if self.api_allowed_origins != other.api_allowed_origins {
+            changes.push(format!(
+                "API allowed origins: {} -> {}",
+                or_none(self.api_allowed_origins.join(", ")),
+                or_none(other.api_allowed_origins.join(", "))
+            ));
+        }
+        if self.webhooks != other.webhooks {
+            changes.push(format!("Webhooks: edited ({} -> {} configured)", self.webhooks.len(), other.webhooks.len()));
+        }
+        if self.hooks != other.hooks {
+            changes.push(format!("Hooks: edited ({} -> {} configured)", self.hooks.len(), other.hooks.len()));
+        }
+        if self.mqtt_username != other.mqtt_username {
+            changes.push(format!(
+                "MQTT username: {} -> {}",
+                or_none(self.mqtt_username.clone()),
+                or_none(other.mqtt_username.clone())
+            ));
+        }
+        if self.mqtt_password != other.mqtt_password {
+            changes.push("MQTT password: changed".to_string());
+        }
+        changes
+    }
+
+    pub fn render(template: &str, window: Option<&str>) -> String {
+        template
+            .replace("{game}", GAME_NAME)
+            .replace("{window}", window.unwrap_or(GAME_NAME))
+            .chars()
+            .take(128)
+            .collect()
+    }
+}
+
+/// When the app relaunches itself with administrator rights.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ElevationPolicy {
+    /// Never ask.
+    Never,
+    /// Ask only when RPCS3 refuses an unelevated read of its window titles.
+    #[default]
+    IfNeeded,
+    /// Ask at every start.
+    Always,
+}
+
+impl ElevationPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ElevationPolicy::Never => "never",
+            ElevationPolicy::IfNeeded => "if-needed",
+            ElevationPolicy::Always => "always",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "never" => Ok(ElevationPolicy::Never),
+            "if-needed" => Ok(ElevationPolicy::IfNeeded),
+            "always" => Ok(ElevationPolicy::Always),
+            _ => Err(format!("'{}' is not never, if-needed or always", value)),
+        }
+    }
+}
+
+/// An HTTP endpoint that receives a templated JSON POST for selected events.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Names from `NOTIFY_EVENTS`.
+    pub events: Vec<String>,
+    /// JSON body; the variables from `AppEvent::vars` are substituted, e.g. `{game}`.
+    pub body: String,
+}
+
+impl Default for WebhookTarget {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            events: vec!["session_started".to_string(), "session_stopped".to_string()],
+            body: WEBHOOK_BODY_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl WebhookTarget {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("Webhook URL '{}' must start with http:// or https://", self.url));
+        }
+        if let Some(event) = self.events.iter().find(|e| !NOTIFY_EVENTS.contains(&e.as_str())) {
+            return Err(format!("Unknown webhook event '{}' (expected one of {})", event, NOTIFY_EVENTS.join(", ")));
+        }
+        render_body(&self.body, &sample_vars())
+            .map(|_| ())
+            .map_err(|e| format!("Webhook body for {} is not valid JSON: {}", self.url, e))
+    }
+}
+
+/// A shell command run when one of `events` happens, with the event described in
+/// `MOTORSTORM_*` environment variables.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HookCommand {
+    pub command: String,
+    /// Names from `NOTIFY_EVENTS`.
+    pub events: Vec<String>,
+    pub timeout_ms: u64,
+}
+
+impl Default for HookCommand {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            events: vec!["session_started".to_string(), "session_stopped".to_string()],
+            timeout_ms: HOOK_TIMEOUT_MS,
+        }
+    }
+}
+
+impl HookCommand {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.command.trim().is_empty() {
+            return Err("Hook command must not be empty".to_string());
+        }
+        if let Some(event) = self.events.iter().find(|e| !NOTIFY_EVENTS.contains(&e.as_str())) {
+            return Err(format!("Unknown hook event '{}' (expected one of {})", event, NOTIFY_EVENTS.join(", ")));
+        }
+        if !(100..=600_000).contains(&self.timeout_ms) {
+            return Err(format!("Hook timeout for '{}' must be between 100 and 600000 ms", self.command));
+        }
+        Ok(())
+    }
+}
+
+/// A manually pinned activity that replaces automatic detection until cleared.
+/// Persisted next to `config.toml` so it survives restarts.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManualOverride {
+    pub details: String,
+    pub state: Option<String>,
+    /// Unix timestamp the elapsed timer counts from, if shown.
+    pub timer_start: Option<i64>,
+}
+
+impl ManualOverride {
+    pub fn new(details: &str, state: Option<&str>, timer: bool) -> Result<Self, String> {
+        let details = details.trim();
+        let state = state.map(str::trim).filter(|s| !s.is_empty());
+        check_activity_text("Details", details)?;
+        if let Some(state) = state {
+            check_activity_text("State", state)?;
+        }
+        Ok(Self {
+            details: details.to_string(),
+            state: state.map(str::to_string),
+            timer_start: timer.then(|| chrono::Utc::now().timestamp()),
+        })
+    }
+
+    pub fn load() -> Result<Option<Self>> {
+        let Some(path) = config_file(OVERRIDE_FILE_NAME) else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let value = toml::from_str(&text)
+            .with_context(|| format!("Invalid override in {}", path.display()))?;
+        Ok(Some(value))
+    }
+
+    /// Persists `value`, or removes the stored override when `None`.
+    pub fn store(value: Option<&Self>) -> Result<()> {
+        match value {
+            Some(value) => {
+                write_config_file(OVERRIDE_FILE_NAME, &toml::to_string_pretty(value)?)?;
+            }
+            None => {
+                if let Some(path) = config_file(OVERRIDE_FILE_NAME).filter(|p| p.exists()) {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to remove {}", path.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The settings exposed in the TUI editor, edited as text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SettingField {
+    PollInterval,
+    StartCooldown,
+    StopCooldown,
+    Keywords,
+    DetailsTemplate,
+    StateTemplate,
+    PresenceScript,
+    ClientId,
+    PreferredEndpoint,
+    RateLimit,
+    RateWindow,
+    AssetsUrl,
+    ApiEnabled,
+    ApiPort,
+    MetricsEnabled,
+    ObsOutputDir,
+    MqttEnabled,
+    MqttHost,
+    MqttPort,
+    MqttTopicPrefix,
+    MqttDiscoveryPrefix,
+    Elevation,
+}
+
+impl SettingField {
+    pub const ALL: &'static [SettingField] = &[
+        SettingField::PollInterval,
+        SettingField::StartCooldown,
+        SettingField::StopCooldown,
+        SettingField::Keywords,
+        SettingField::DetailsTemplate,
+        SettingField::StateTemplate,
+        SettingField::PresenceScript,
+        SettingField::ClientId,
+        SettingField::PreferredEndpoint,
+        SettingField::RateLimit,
+        SettingField::RateWindow,
+        SettingField::AssetsUrl,
+        SettingField::ApiEnabled,
+        SettingField::ApiPort,
+        SettingField::MetricsEnabled,
+        SettingField::ObsOutputDir,
+        SettingField::MqttEnabled,
+        SettingField::MqttHost,
+        SettingField::MqttPort,
+        SettingField::MqttTopicPrefix,
+        SettingField::MqttDiscoveryPrefix,
+        SettingField::Elevation,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SettingField::PollInterval => "Poll interval (ms)",
+            SettingField::StartCooldown => "Start cooldown (ms)",
+            SettingField::StopCooldown => "Stop cooldown (ms)",
+            SettingField::Keywords => "Window keywords",
+            SettingField::DetailsTemplate => "Details template",
+            SettingField::StateTemplate => "State template",
+            SettingField::PresenceScript => "Presence script",
+            SettingField::ClientId => "Discord client ID",
+            SettingField::PreferredEndpoint => "Preferred endpoint",
+            SettingField::RateLimit => "Activity updates per window",
+            SettingField::RateWindow => "Activity rate window (ms)",
+            SettingField::AssetsUrl => "Assets URL",
+            SettingField::ApiEnabled => "Local HTTP API (on/off)",
+            SettingField::ApiPort => "HTTP API port",
+            SettingField::MetricsEnabled => "Prometheus /metrics (on/off)",
+            SettingField::ObsOutputDir => "OBS output directory",
+            SettingField::MqttEnabled => "MQTT publishing (on/off)",
+            SettingField::MqttHost => "MQTT broker host",
+            SettingField::MqttPort => "MQTT broker port",
+            SettingField::MqttTopicPrefix => "MQTT topic prefix",
+            SettingField::MqttDiscoveryPrefix => "MQTT discovery prefix",
+            SettingField::Elevation => "Elevation (never/if-needed/always)",
+        }
+    }
+
+    pub fn get(self, s: &Settings) -> String {
+        match self {
+            SettingField::PollInterval => s.poll_interval_ms.to_string(),
+            SettingField::StartCooldown => s.start_cooldown_ms.to_string(),
+            SettingField::StopCooldown => s.stop_cooldown_ms.to_string(),
+            SettingField::Keywords => s.keywords.join(", "),
+            SettingField::DetailsTemplate => s.details_template.clone(),
+            SettingField::StateTemplate => s.state_template.clone(),
+            SettingField::PresenceScript => s.presence_script.clone(),
+            SettingField::ClientId => s.client_id.clone(),
+            SettingField::PreferredEndpoint => s.preferred_endpoint.clone(),
+            SettingField::RateLimit => s.activity_rate_limit.to_string(),
+            SettingField::RateWindow => s.activity_rate_window_ms.to_string(),
+            SettingField::AssetsUrl => s.assets_url.clone(),
+            SettingField::ApiEnabled => on_off(s.api_enabled),
+            SettingField::ApiPort => s.api_port.to_string(),
+            SettingField::MetricsEnabled => on_off(s.metrics_enabled),
+            SettingField::ObsOutputDir => s.obs_output_dir.clone(),
+            SettingField::MqttEnabled => on_off(s.mqtt_enabled),
+            SettingField::MqttHost => s.mqtt_host.clone(),
+            SettingField::MqttPort => s.mqtt_port.to_string(),
+            SettingField::MqttTopicPrefix => s.mqtt_topic_prefix.clone(),
+            SettingField::MqttDiscoveryPrefix => s.mqtt_discovery_prefix.clone(),
+            SettingField::Elevation => s.elevation.as_str().to_string(),
+        }
+    }
+
+    /// Parses `value` into `s`, leaving it untouched when the value is invalid.
+    pub fn set(self, s: &mut Settings, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let mut updated = s.clone();
+        match self {
+            SettingField::PollInterval => updated.poll_interval_ms = parse_number(value)?,
+            SettingField::StartCooldown => updated.start_cooldown_ms = parse_number(value)?,
+            SettingField::StopCooldown => updated.stop_cooldown_ms = parse_number(value)?,
+            SettingField::Keywords => {
+                updated.keywords = value
+                    .split(',')
+                    .map(|k| k.trim().to_lowercase())
+                    .filter(|k| !k.is_empty())
+                    .collect();
+            }
+            SettingField::DetailsTemplate => updated.details_template = value.to_string(),
+            SettingField::StateTemplate => updated.state_template = value.to_string(),
+            SettingField::PresenceScript => updated.presence_script = value.to_string(),
+            SettingField::ClientId => updated.client_id = value.to_string(),
+            SettingField::PreferredEndpoint => updated.preferred_endpoint = value.to_string(),
+            SettingField::RateLimit => updated.activity_rate_limit = parse_number(value)?,
+            SettingField::RateWindow => updated.activity_rate_window_ms = parse_number(value)?,
+            SettingField::AssetsUrl => updated.assets_url = value.to_string(),
+            SettingField::ApiEnabled => updated.api_enabled = parse_on_off(value)?,
+            SettingField::ApiPort => updated.api_port = parse_number(value)?,
+            SettingField::MetricsEnabled => updated.metrics_enabled = parse_on_off(value)?,
+            SettingField::ObsOutputDir => updated.obs_output_dir = value.to_string(),
+            SettingField::MqttEnabled => updated.mqtt_enabled = parse_on_off(value)?,
+            SettingField::MqttHost => updated.mqtt_host = value.to_string(),
+            SettingField::MqttPort => updated.mqtt_port = parse_number(value)?,
+            SettingField::MqttTopicPrefix => updated.mqtt_topic_prefix = value.trim_matches('/').to_string(),
+            SettingField::MqttDiscoveryPrefix => updated.mqtt_discovery_prefix = value.trim_matches('/').to_string(),
+            SettingField::Elevation => updated.elevation = ElevationPolicy::parse(value)?,
+        }
+        self.validate(&updated)?;
+        *s = updated;
+        Ok(())
+    }
+
+    pub fn validate(self, s: &Settings) -> Result<(), String> {
+        match self {
+            SettingField::PollInterval => check_range(self, s.poll_interval_ms as i64, 500, 60_000),
+            SettingField::StartCooldown => check_range(self, s.start_cooldown_ms, 0, 600_000),
+            SettingField::StopCooldown => check_range(self, s.stop_cooldown_ms, 0, 600_000),
+            SettingField::RateLimit => check_range(self, s.activity_rate_limit as i64, 1, 5),
+            SettingField::RateWindow => check_range(self, s.activity_rate_window_ms as i64, 1000, 600_000),
+            SettingField::Keywords if s.keywords.is_empty() => {
+                Err("At least one window keyword is required".to_string())
+            }
+            SettingField::DetailsTemplate => check_template(self, &s.details_template),
+            SettingField::StateTemplate => check_template(self, &s.state_template),
+            SettingField::ClientId => {
+                let id = &s.client_id;
+                if (17..=20).contains(&id.len()) && id.chars().all(|c| c.is_ascii_digit()) {
+                    Ok(())
+                } else {
+                    Err("Client ID must be a 17-20 digit application ID".to_string())
+                }
+            }
+            SettingField::ApiPort => check_range(self, s.api_port as i64, 1024, 65535),
+            SettingField::AssetsUrl if !s.assets_url.starts_with("http://") && !s.assets_url.starts_with("https://") => {
+                Err("Assets URL must start with http:// or https://".to_string())
+            }
+            SettingField::MqttHost if s.mqtt_host.trim().is_empty() => Err("MQTT broker host is required".to_string()),
+            SettingField::MqttPort => check_range(self, s.mqtt_port as i64, 1, 65535),
+            SettingField::MqttTopicPrefix if s.mqtt_topic_prefix.is_empty() => {
+                Err("MQTT topic prefix is required".to_string())
+            }
+            SettingField::MqttTopicPrefix => check_topic(self, &s.mqtt_topic_prefix),
+            SettingField::MqttDiscoveryPrefix => check_topic(self, &s.mqtt_discovery_prefix),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("'{}' is not a valid number", value))
+}
+
+fn on_off(value: bool) -> String {
+    if value { "on" } else { "off" }.to_string()
+}
+
+fn parse_on_off(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("'{}' is not on or off", value)),
+    }
+}
+
+fn check_range(field: SettingField, value: i64, min: i64, max: i64) -> Result<(), String> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} must be between {} and {}", field.label(), min, max))
+    }
+}
+
+// Prefixes are published to, so MQTT wildcards aren't allowed
+fn check_topic(field: SettingField, topic: &str) -> Result<(), String> {
+    if topic.contains(['+', '#']) || topic.starts_with('/') || topic.ends_with('/') {
+        Err(format!("{} must not contain + or # or start/end with /", field.label()))
+    } else {
+        Ok(())
+    }
+}
+
+// Discord rejects activity strings shorter than 2 or longer than 128 characters
+pub fn check_activity_text(label: &str, text: &str) -> Result<(), String> {
+    if (2..=128).contains(&text.chars().count()) {
+        Ok(())
+    } else {
+        Err(format!("{} must be 2-128 characters", label))
+    }
+}
+
+fn check_template(field: SettingField, template: &str) -> Result<(), String> {
+    check_activity_text(field.label(), &template.replace("{game}", GAME_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_leaves_a_file_that_failed_to_load_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        let text = "poll_interval_ms = \"fast\"\nmqtt_host = \"broker.lan\"\n\n[[webhooks]]\nurl = \"http://127.0.0.1:9000/hook\"\n";
+        fs::write(&path, text).unwrap();
+
+        assert!(Settings::load_from(&path).is_err());
+        let settings = Settings { api_enabled: true, ..Default::default() };
+        let error = settings.save_to(&path).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("fix or remove it"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+    }
+
+    #[test]
+    fn diff_covers_fields_edited_in_the_file_only() {
+        let current = Settings::default();
+        let updated = Settings {
+            poll_interval_ms: 5000,
+            api_allowed_origins: vec!["https://widgets.example".to_string()],
+            webhooks: vec![WebhookTarget { url: "http://127.0.0.1:9000/hook".to_string(), ..Default::default() }],
+            mqtt_username: "racer".to_string(),
+            mqtt_password: "secret".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            current.diff(&updated),
+            vec![
+                "Poll interval (ms): 3000 -> 5000",
+                "API allowed origins: (none) -> https://widgets.example",
+                "Webhooks: edited (0 -> 1 configured)",
+                "MQTT username: (none) -> racer",
+                "MQTT password: changed",
+            ]
+        );
+        assert!(current.diff(&current.clone()).is_empty());
+    }
+
+    #[test]
+    fn save_round_trips_a_valid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+
+        let mut settings = Settings { mqtt_host: "broker.lan".to_string(), ..Default::default() };
+        settings.save_to(&path).unwrap();
+        settings.api_enabled = true;
+        settings.save_to(&path).unwrap();
+
+        assert_eq!(Settings::load_from(&path).unwrap(), settings);
+    }
+}
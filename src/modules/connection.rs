@@ -0,0 +1,552 @@
+use crate::modules::config::{
+    ACTIVITY_RATE_LIMIT, ACTIVITY_RATE_WINDOW_MS, RECONNECT_BASE_MS, RECONNECT_JITTER, RECONNECT_MAX_MS,
+};
+use crate::modules::discord::{DiscordClient, Presence};
+use crate::modules::ipc::{Activity, IpcError};
+use chrono::Utc;
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// Capped exponential backoff with jitter for reconnect attempts.
+pub struct Backoff {
+    attempt: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self { attempt: 0, next_attempt: None }
+    }
+
+    /// Delay before retry number `attempt` (0-based), without jitter.
+    pub fn base_delay(attempt: u32) -> Duration {
+        let ms = RECONNECT_BASE_MS.saturating_mul(1u64 << attempt.min(16));
+        Duration::from_millis(ms.min(RECONNECT_MAX_MS))
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|at| now >= at)
+    }
+
+    /// Records a failed attempt and returns how long until the next one.
+    pub fn fail(&mut self, now: Instant) -> Duration {
+        let delay = jitter(Self::base_delay(self.attempt));
+        self.attempt = self.attempt.saturating_add(1);
+        self.next_attempt = Some(now + delay);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+        self.next_attempt = None;
+    }
+}
+
+// Spreads retries by up to ±RECONNECT_JITTER so several instances don't reconnect in lockstep
+fn jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let unit = (random % 10_000) as f64 / 10_000.0; // 0.0..1.0
+    delay.mul_f64(1.0 + RECONNECT_JITTER * (unit * 2.0 - 1.0))
+}
+
+/// Sliding-window limit on how many activity updates go out.
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self { max: max.max(1), window, sent: VecDeque::new() }
+    }
+
+    pub fn set_limit(&mut self, max: u32, window: Duration) {
+        self.max = max.max(1);
+        self.window = window;
+    }
+
+    /// How long until another update may go out; zero when allowed now.
+    pub fn wait_time(&mut self, now: Instant) -> Duration {
+        while self.sent.front().is_some_and(|at| now.duration_since(*at) >= self.window) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() < self.max as usize {
+            return Duration::ZERO;
+        }
+        self.sent.front().map_or(Duration::ZERO, |at| (*at + self.window).saturating_duration_since(now))
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.sent.push_back(now);
+    }
+}
+
+/// Counts of activity updates, for debug output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateStats {
+    pub sent: u64,
+    /// Updates dropped because they repeated the last payload or were superseded before going out.
+    pub suppressed: u64,
+    /// Updates Discord rejected or that failed on a broken connection.
+    pub failed: u64,
+}
+
+/// The last activity Discord accepted; `activity` is `None` when it was a clear.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentActivity {
+    pub activity: Option<Activity>,
+    /// Unix timestamp of when Discord acknowledged it.
+    pub sent_at: i64,
+}
+
+/// The most recent connect or update failure.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LastError {
+    pub message: String,
+    pub at: i64,
+}
+
+/// What happened during a call to the connection manager, for logging.
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    Connected { resent: bool, elapsed: Duration },
+    ConnectFailed { error: IpcError, retry_in: Duration, elapsed: Duration },
+    /// The connection broke; a reconnect is scheduled.
+    Disconnected { error: IpcError, retry_in: Duration },
+    ActivitySent { cleared: bool, elapsed: Duration },
+    /// The rate limit was hit; the latest activity goes out after `retry_in`.
+    ActivityDeferred { retry_in: Duration },
+    /// Discord rejected the activity but the connection is still usable.
+    ActivityRejected { error: IpcError },
+}
+
+/// Owns the `DiscordClient`, keeps it connected and makes sure Discord shows
+/// the current activity, including after a reconnect. Updates that would not
+/// change what Discord shows are dropped, and the rest are rate-limited.
+pub struct ConnectionManager {
+    client: DiscordClient,
+    backoff: Backoff,
+    limiter: RateLimiter,
+    /// Activity that should be visible; `None` means cleared.
+    current: Option<Presence>,
+    /// Whether `current` still has to be sent over the live connection.
+    dirty: bool,
+    /// Rendered payload Discord is known to show (`Null` when cleared); `None` when unknown.
+    last_sent: Option<Value>,
+    /// Whether the pending update was already reported as deferred.
+    deferred: bool,
+    /// A clear that failed while pausing; retried on `tick` until it goes through
+    /// or a fresh connection makes it unnecessary.
+    clear_pending: bool,
+    stats: UpdateStats,
+    last_activity: Option<SentActivity>,
+    last_error: Option<LastError>,
+}
+
+impl ConnectionManager {
+    pub fn new(client: DiscordClient) -> Self {
+        Self {
+            client,
+            backoff: Backoff::new(),
+            limiter: RateLimiter::new(ACTIVITY_RATE_LIMIT, Duration::from_millis(ACTIVITY_RATE_WINDOW_MS)),
+            current: None,
+            dirty: false,
+            last_sent: None,
+            deferred: false,
+            clear_pending: false,
+            stats: UpdateStats::default(),
+            last_activity: None,
+            last_error: None,
+        }
+    }
+
+    pub fn set_rate_limit(&mut self, max: u32, window: Duration) {
+        self.limiter.set_limit(max, window);
+    }
+
+    pub fn stats(&self) -> UpdateStats {
+        self.stats
+    }
+
+    pub fn last_activity(&self) -> Option<&SentActivity> {
+        self.last_activity.as_ref()
+    }
+
+    pub fn last_error(&self) -> Option<&LastError> {
+        self.last_error.as_ref()
+    }
+
+    fn record_error(&mut self, error: &IpcError) {
+        self.last_error = Some(LastError { message: error.to_string(), at: Utc::now().timestamp() });
+    }
+
+    pub fn client(&self) -> &DiscordClient {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut DiscordClient {
+        &mut self.client
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    /// Skips the backoff so a connection dropped on purpose (e.g. client ID change) is retried at once.
+    pub fn reconnect_now(&mut self) {
+        self.backoff.reset();
+        self.dirty = true;
+    }
+
+    /// Sets the activity to show and sends it if connected and changed.
+    pub fn set_activity(&mut self, presence: Option<Presence>, now: Instant) -> Vec<ConnectionEvent> {
+        if presence != self.current {
+            if self.dirty {
+                // The previous update never went out and now never will
+                self.stats.suppressed += 1;
+            }
+            self.current = presence;
+            self.dirty = true;
+        }
+        self.flush(now)
+    }
+
+    /// Clears the activity and withholds updates until `resume`. Updates stay
+    /// withheld when the clear fails; it is retried on the next `tick`.
+    pub fn pause(&mut self) -> Result<(), IpcError> {
+        let result = self.client.pause();
+        self.after_clear(&result);
+        result
+    }
+
+    fn after_clear(&mut self, result: &Result<(), IpcError>) {
+        match result {
+            Ok(()) => {
+                self.clear_pending = false;
+                if self.client.is_connected() {
+                    self.last_sent = Some(Value::Null);
+                    self.last_activity = Some(SentActivity { activity: None, sent_at: Utc::now().timestamp() });
+                }
+            }
+            Err(error) => {
+                self.record_error(error);
+                self.last_sent = None;
+                // A dropped connection shows nothing once it is re-established
+                self.clear_pending = self.client.is_connected();
+            }
+        }
+    }
+
+    /// Allows updates again and restores the current activity.
+    pub fn resume(&mut self, now: Instant) -> Vec<ConnectionEvent> {
+        self.clear_pending = false;
+        self.client.resume();
+        self.dirty = true;
+        self.flush(now)
+    }
+
+    /// Reconnects when due, then sends any pending activity.
+    pub fn tick(&mut self, now: Instant) -> Vec<ConnectionEvent> {
+        let mut events = Vec::new();
+        if !self.client.is_connected() && self.backoff.is_due(now) {
+            let started = Instant::now();
+            match self.client.connect() {
+                Ok(()) => {
+                    self.backoff.reset();
+                    // A fresh connection starts without activity
+                    self.clear_pending = false;
+                    let resent = self.current.is_some();
+                    self.dirty = resent;
+                    self.last_sent = Some(Value::Null);
+                    events.push(ConnectionEvent::Connected { resent, elapsed: started.elapsed() });
+                }
+                Err(error) => {
+                    self.record_error(&error);
+                    let retry_in = self.backoff.fail(now);
+                    events.push(ConnectionEvent::ConnectFailed { error, retry_in, elapsed: started.elapsed() });
+                }
+            }
+        }
+        if self.clear_pending && self.client.is_paused() {
+            let result = self.client.clear_presence();
+            self.after_clear(&result);
+        }
+        events.extend(self.flush(now));
+        events
+    }
+
+    fn flush(&mut self, now: Instant) -> Vec<ConnectionEvent> {
+        if !self.dirty || !self.client.is_connected() || self.client.is_paused() {
+            return Vec::new();
+        }
+
+        let payload = match &self.current {
+            Some(presence) => serde_json::to_value(presence.to_activity()).unwrap_or_default(),
+            None => Value::Null,
+        };
+        if self.last_sent.as_ref() == Some(&payload) {
+            self.dirty = false;
+            self.deferred = false;
+            self.stats.suppressed += 1;
+            return Vec::new();
+        }
+
+        // Leave the update pending; `tick` sends the latest one once the window allows
+        let wait = self.limiter.wait_time(now);
+        if !wait.is_zero() {
+            if self.deferred {
+                return Vec::new();
+            }
+            self.deferred = true;
+            return vec![ConnectionEvent::ActivityDeferred { retry_in: wait }];
+        }
+        self.deferred = false;
+        self.limiter.record(now);
+
+        let started = Instant::now();
+        let result = match &self.current {
+            Some(presence) => self.client.update_presence(presence),
+            None => self.client.clear_presence(),
+        };
+        match result {
+            Ok(()) => {
+                self.dirty = false;
+                self.last_sent = Some(payload);
+                self.stats.sent += 1;
+                self.last_activity = Some(SentActivity {
+                    activity: self.current.as_ref().map(Presence::to_activity),
+                    sent_at: Utc::now().timestamp(),
+                });
+                vec![ConnectionEvent::ActivitySent { cleared: self.current.is_none(), elapsed: started.elapsed() }]
+            }
+            Err(error) if self.client.is_connected() => {
+                // Retrying the same payload won't help
+                self.dirty = false;
+                self.last_sent = None;
+                self.record_error(&error);
+                self.stats.failed += 1;
+                vec![ConnectionEvent::ActivityRejected { error }]
+            }
+            Err(error) => {
+                self.last_sent = None;
+                self.record_error(&error);
+                self.stats.failed += 1;
+                let retry_in = self.backoff.fail(now);
+                vec![ConnectionEvent::Disconnected { error, retry_in }]
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::modules::mock_ipc::{Fault, MockDiscord};
+
+    const CLIENT_ID: &str = "1164946062499389470";
+    const WAIT: Duration = Duration::from_secs(2);
+
+    fn presence(details: &str) -> Presence {
+        Presence { details: details.to_string(), state: None, start_time: None }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_and_caps() {
+        assert_eq!(Backoff::base_delay(0), Duration::from_millis(RECONNECT_BASE_MS));
+        assert_eq!(Backoff::base_delay(1), Duration::from_millis(RECONNECT_BASE_MS * 2));
+        assert_eq!(Backoff::base_delay(30), Duration::from_millis(RECONNECT_MAX_MS));
+
+        let mut backoff = Backoff::new();
+        let now = Instant::now();
+        assert!(backoff.is_due(now));
+        let delay = backoff.fail(now);
+        let base = RECONNECT_BASE_MS as f64;
+        assert!(delay.as_millis() as f64 >= base * (1.0 - RECONNECT_JITTER) - 1.0);
+        assert!(delay.as_millis() as f64 <= base * (1.0 + RECONNECT_JITTER) + 1.0);
+        assert!(!backoff.is_due(now));
+        assert!(backoff.is_due(now + delay));
+    }
+
+    #[test]
+    fn failed_connect_schedules_retry_instead_of_hammering() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = crate::modules::mock_ipc::use_runtime_dir(dir.path());
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        let now = Instant::now();
+
+        let events = manager.tick(now);
+        assert!(matches!(events[0], ConnectionEvent::ConnectFailed { error: IpcError::NotRunning, .. }));
+        // Not due yet: no second attempt
+        assert!(manager.tick(now).is_empty());
+    }
+
+    #[test]
+    fn broken_pipe_marks_disconnected_and_resends_after_reconnect() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        let now = Instant::now();
+
+        manager.tick(now);
+        manager.set_activity(Some(presence("Racing")), now);
+        assert_eq!(mock.wait_for_activities(1, WAIT).len(), 1);
+
+        mock.inject(Fault::Disconnect);
+        let events = manager.set_activity(Some(presence("Still racing")), now);
+        assert!(matches!(events[0], ConnectionEvent::Disconnected { .. }));
+        assert!(!manager.is_connected());
+
+        // Retry only once the backoff elapsed
+        assert!(manager.tick(now).is_empty());
+        let later = now + Duration::from_millis(RECONNECT_MAX_MS);
+        let events = manager.tick(later);
+        assert!(matches!(events[0], ConnectionEvent::Connected { resent: true, .. }));
+        assert!(matches!(events[1], ConnectionEvent::ActivitySent { cleared: false, .. }));
+
+        let activities = mock.wait_for_activities(2, WAIT);
+        assert_eq!(activities.last().unwrap()["details"], "Still racing");
+        assert_eq!(mock.handshakes().len(), 2);
+    }
+
+    #[test]
+    fn rejected_activity_keeps_connection() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        let now = Instant::now();
+
+        manager.tick(now);
+        mock.inject(Fault::Error { code: 4002, message: "bad activity".to_string() });
+        let events = manager.set_activity(Some(presence("Racing")), now);
+
+        assert!(matches!(events[0], ConnectionEvent::ActivityRejected { .. }));
+        assert!(manager.is_connected());
+        assert!(manager.last_error().unwrap().message.contains("bad activity"));
+        assert!(manager.last_activity().is_none());
+
+        manager.set_activity(Some(presence("Racing again")), now);
+        let sent = manager.last_activity().unwrap();
+        assert_eq!(sent.activity.as_ref().unwrap().details.as_deref(), Some("Racing again"));
+    }
+
+    #[test]
+    fn identical_payloads_are_sent_once() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        let now = Instant::now();
+
+        manager.tick(now);
+        manager.set_activity(Some(presence("Racing")), now);
+        manager.set_activity(Some(presence("Menu")), now);
+        manager.set_activity(Some(presence("Racing")), now);
+        manager.set_activity(Some(presence("Racing")), now);
+
+        assert_eq!(mock.wait_for_activities(3, WAIT).len(), 3);
+        // The fourth call matched the current presence and didn't count
+        assert_eq!(manager.stats(), UpdateStats { sent: 3, suppressed: 0, failed: 0 });
+
+        manager.set_activity(None, now);
+        manager.set_activity(Some(presence("Racing")), now);
+        assert_eq!(manager.stats().sent, 5);
+    }
+
+    #[test]
+    fn rate_limit_defers_and_sends_only_the_latest() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        manager.set_rate_limit(2, Duration::from_secs(20));
+        let now = Instant::now();
+
+        manager.tick(now);
+        manager.set_activity(Some(presence("Lap 1")), now);
+        manager.set_activity(Some(presence("Lap 2")), now);
+        let events = manager.set_activity(Some(presence("Lap 3")), now);
+        assert!(matches!(events[0], ConnectionEvent::ActivityDeferred { .. }));
+        // Deferral is reported once per pending update
+        assert!(manager.set_activity(Some(presence("Lap 4")), now).is_empty());
+        assert!(manager.tick(now + Duration::from_secs(10)).is_empty());
+
+        let events = manager.tick(now + Duration::from_secs(20));
+        assert!(matches!(events[0], ConnectionEvent::ActivitySent { .. }));
+
+        let activities = mock.wait_for_activities(3, WAIT);
+        let details: Vec<_> = activities.iter().map(|a| a["details"].as_str().unwrap()).collect();
+        assert_eq!(details, ["Lap 1", "Lap 2", "Lap 4"]);
+        assert_eq!(manager.stats(), UpdateStats { sent: 3, suppressed: 1, failed: 0 });
+    }
+
+    #[test]
+    fn returning_to_the_shown_payload_cancels_a_deferred_update() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        manager.set_rate_limit(1, Duration::from_secs(20));
+        let now = Instant::now();
+
+        manager.tick(now);
+        manager.set_activity(Some(presence("Racing")), now);
+        manager.set_activity(Some(presence("Paused menu")), now);
+        manager.set_activity(Some(presence("Racing")), now);
+
+        assert!(manager.tick(now + Duration::from_secs(20)).is_empty());
+        assert_eq!(mock.wait_for_activities(1, WAIT).len(), 1);
+        assert_eq!(manager.stats(), UpdateStats { sent: 1, suppressed: 2, failed: 0 });
+    }
+
+    #[test]
+    fn pause_holds_when_the_pipe_breaks_during_the_clear() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        let now = Instant::now();
+
+        manager.tick(now);
+        manager.set_activity(Some(presence("Racing")), now);
+        mock.wait_for_activities(1, WAIT);
+
+        mock.inject(Fault::Disconnect);
+        assert!(manager.pause().is_err());
+        assert!(manager.client().is_paused());
+        assert!(!manager.is_connected());
+
+        manager.set_activity(Some(presence("Still racing")), now);
+        let events = manager.tick(now + Duration::from_millis(RECONNECT_MAX_MS));
+        assert!(matches!(events[0], ConnectionEvent::Connected { .. }));
+        assert_eq!(events.len(), 1);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(mock.activities().len(), 1);
+    }
+
+    #[test]
+    fn pause_retries_a_rejected_clear() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let mut manager = ConnectionManager::new(DiscordClient::new(CLIENT_ID));
+        let now = Instant::now();
+
+        manager.tick(now);
+        manager.set_activity(Some(presence("Racing")), now);
+        mock.wait_for_activities(1, WAIT);
+
+        mock.inject(Fault::Error { code: 5000, message: "busy".to_string() });
+        assert!(manager.pause().is_err());
+        assert!(manager.is_connected());
+        assert!(manager.client().is_paused());
+
+        manager.set_activity(Some(presence("Still racing")), now);
+        manager.tick(now);
+        let activities = mock.wait_for_activities(2, WAIT);
+        assert_eq!(activities[0]["details"], "Racing");
+        assert!(activities[1].is_null());
+        // Cleared now, so later ticks don't send again
+        manager.tick(now);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(mock.activities().len(), 2);
+    }
+}
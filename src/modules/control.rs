@@ -0,0 +1,544 @@
+//! Control socket: lets scripts and hotkeys drive a running instance.
+//!
+//! The app listens on a Unix socket (in the user's runtime directory) or a
+//! named pipe on Windows. Each request is one JSON object per line, named by
+//! `cmd`, and gets one JSON line back:
+//!
+//! ```text
+//! > {"cmd": "override", "details": "Practising Lap Times", "timer": true}
+//! < {"ok": true, "status": {...}}
+//! ```
+//!
+//! `override` without `details` clears the manual activity. Failures answer
+//! `{"ok": false, "error": "..."}`. `motorstormrpc ctl <cmd>` is the client.
+
+use crate::modules::api::status_json;
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::cli::{Cli, CtlCommand};
+use crate::modules::config::{ManualOverride, Settings, TAKEOVER_TIMEOUT_MS};
+use crate::modules::window::{allow_foreground_handoff, bring_console_to_front};
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Followed by the user's SID, so each user gets their own instance.
+#[cfg(windows)]
+const PIPE_PREFIX: &str = r"\\.\pipe\motorstormrpc-control-";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    Status,
+    Pause,
+    Resume,
+    /// Pins a manual activity, or clears it when `details` is missing.
+    Override {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        state: Option<String>,
+        #[serde(default)]
+        timer: bool,
+    },
+    /// Re-reads `config.toml`; the background task applies and logs the changes.
+    ReloadConfig,
+    /// Raises the console window; sent by a second launch.
+    Focus,
+    Quit,
+}
+
+impl From<CtlCommand> for ControlRequest {
+    fn from(cmd: CtlCommand) -> Self {
+        match cmd {
+            CtlCommand::Status => ControlRequest::Status,
+            CtlCommand::Pause => ControlRequest::Pause,
+            CtlCommand::Resume => ControlRequest::Resume,
+            CtlCommand::Override { details, state, timer, .. } => ControlRequest::Override { details, state, timer },
+            CtlCommand::ReloadConfig => ControlRequest::ReloadConfig,
+            CtlCommand::Focus => ControlRequest::Focus,
+            CtlCommand::Quit => ControlRequest::Quit,
+        }
+    }
+}
+
+/// Carries out `request`, returning the response object.
+pub fn handle(app_state: &Mutex<AppState>, running: &AtomicBool, request: ControlRequest) -> Value {
+    let result = match request {
+        ControlRequest::Status => Ok(()),
+        ControlRequest::Pause => {
+            app_state.lock().set_paused(true);
+            Ok(())
+        }
+        ControlRequest::Resume => {
+            app_state.lock().set_paused(false);
+            Ok(())
+        }
+        ControlRequest::Override { details: None, .. } => {
+            app_state.lock().set_override(None);
+            Ok(())
+        }
+        ControlRequest::Override { details: Some(details), state, timer } => {
+            ManualOverride::new(&details, state.as_deref(), timer).map(|manual| app_state.lock().set_override(Some(manual)))
+        }
+        ControlRequest::ReloadConfig => match Settings::load() {
+            Ok(settings) => {
+                let mut state = app_state.lock();
+                state.settings = settings;
+                state.add_log(LogLevel::Info, "Config reloaded via control socket".to_string());
+                Ok(())
+            }
+            Err(e) => Err(format!("{:#}", e)),
+        },
+        ControlRequest::Focus => {
+            bring_console_to_front();
+            Ok(())
+        }
+        ControlRequest::Quit => {
+            app_state.lock().add_log(LogLevel::Info, "Quit requested via control socket".to_string());
+            running.store(false, Ordering::Relaxed);
+            return json!({ "ok": true });
+        }
+    };
+    match result {
+        Ok(()) => json!({ "ok": true, "status": status_json(&app_state.lock()) }),
+        Err(e) => json!({ "ok": false, "error": e }),
+    }
+}
+
+/// Answers requests on one connection until the client hangs up.
+async fn serve<S: AsyncRead + AsyncWrite>(stream: S, app_state: Arc<Mutex<AppState>>, running: Arc<AtomicBool>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => handle(&app_state, &running, request),
+            Err(e) => json!({ "ok": false, "error": format!("Invalid request: {}", e) }),
+        };
+        if writer.write_all(format!("{}\n", response).as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Returned by `start` when another instance owns the endpoint.
+#[derive(Debug)]
+pub struct InUse(pub String);
+
+impl std::fmt::Display for InUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is in use by another instance", self.0)
+    }
+}
+
+impl std::error::Error for InUse {}
+
+/// A listening control socket; stops when dropped. Only one can exist per
+/// endpoint, which is what keeps a second instance from starting.
+pub struct ControlServer {
+    pub endpoint: String,
+    task: JoinHandle<()>,
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.endpoint);
+    }
+}
+
+/// The socket path or pipe name this user's instance listens on.
+pub fn endpoint() -> Result<String> {
+    #[cfg(unix)]
+    {
+        let path = crate::modules::config::runtime_file("control.sock").context("No runtime directory available")?;
+        Ok(path.display().to_string())
+    }
+    #[cfg(windows)]
+    {
+        let sid = crate::modules::admin::current_user_sid().context("Failed to look up the current user")?;
+        Ok(format!("{}{}", PIPE_PREFIX, sid))
+    }
+}
+
+/// Listens on `endpoint` in the background. Must be called from within the Tokio runtime.
+#[cfg(unix)]
+pub fn start(endpoint: &str, app_state: Arc<Mutex<AppState>>, running: Arc<AtomicBool>) -> Result<ControlServer> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::path::Path;
+
+    let path = Path::new(endpoint);
+    let dir = path.parent().context("Control socket path has no directory")?;
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+
+    // Bind inside a private directory and link the socket into place only once it
+    // is 0600, so it is never connectable by other users, whatever the umask
+    let name = path.file_name().context("Control socket path has no file name")?;
+    static STAGED: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let serial = STAGED.fetch_add(1, Ordering::Relaxed);
+    let staging = dir.join(format!(".{}.{}.{}", name.to_string_lossy(), std::process::id(), serial));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("control.sock");
+    let bound = tokio::net::UnixListener::bind(&staged)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Failed to listen on {}", endpoint))
+        .and_then(|listener| claim(&staged, path).map(|()| listener));
+    let _ = std::fs::remove_dir_all(&staging);
+    let listener = bound?;
+
+    let task = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream, app_state.clone(), running.clone()));
+        }
+    });
+    Ok(ControlServer { endpoint: endpoint.to_string(), task })
+}
+
+/// Links the bound socket at `staged` to `path`. Never replaces a file already
+/// there, so of two launches racing for the same path exactly one wins.
+#[cfg(unix)]
+fn claim(staged: &std::path::Path, path: &std::path::Path) -> Result<()> {
+    let endpoint = path.display().to_string();
+    // A second attempt only follows removing a stale socket
+    for _ in 0..2 {
+        match std::fs::hard_link(staged, path) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if !remove_if_stale(path).with_context(|| format!("Failed to remove stale {}", endpoint))? {
+                    break;
+                }
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to listen on {}", endpoint)),
+        }
+    }
+    Err(InUse(endpoint).into())
+}
+
+/// Removes the socket at `path` if nobody answers on it, as after a crash.
+/// Returns false if it is live.
+#[cfg(unix)]
+fn remove_if_stale(path: &std::path::Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let inode = |path| std::fs::symlink_metadata(path).map(|m| (m.dev(), m.ino()));
+    let before = match inode(path) {
+        Ok(id) => id,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Ok(false);
+    }
+    // Only the file that didn't answer, not one a racing launch linked meanwhile
+    if inode(path).ok() == Some(before) {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(true)
+}
+
+/// Listens on `endpoint` in the background. Must be called from within the Tokio runtime.
+#[cfg(windows)]
+pub fn start(endpoint: &str, app_state: Arc<Mutex<AppState>>, running: Arc<AtomicBool>) -> Result<ControlServer> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = endpoint.to_string();
+    let mut server = match ServerOptions::new().first_pipe_instance(true).create(&name) {
+        Ok(server) => server,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => return Err(InUse(endpoint.to_string()).into()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to listen on {}", endpoint)),
+    };
+    let task = tokio::spawn(async move {
+        loop {
+            if server.connect().await.is_err() {
+                return;
+            }
+            // Open the next instance before serving, so clients never find the pipe missing
+            let connected = server;
+            server = match ServerOptions::new().create(&name) {
+                Ok(next) => next,
+                Err(_) => return,
+            };
+            tokio::spawn(serve(connected, app_state.clone(), running.clone()));
+        }
+    });
+    Ok(ControlServer { endpoint: endpoint.to_string(), task })
+}
+
+/// Sends one request to the instance at `endpoint` and returns its response.
+pub async fn send(endpoint: &str, request: &ControlRequest) -> Result<Value> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(endpoint).await;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(endpoint);
+    let stream = stream.with_context(|| format!("No running instance found at {}", endpoint))?;
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer.write_all(format!("{}\n", serde_json::to_string(request)?).as_bytes()).await?;
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .context("The instance closed the connection without answering")?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// What a second launch asks of the running instance: its `--override-*`,
+/// `--clear-override` and `--paused` flags, then to come to the front.
+pub fn forwarded_requests(cli: &Cli) -> Vec<ControlRequest> {
+    let mut requests = Vec::new();
+    if cli.clear_override {
+        requests.push(ControlRequest::Override { details: None, state: None, timer: false });
+    } else if let Some(details) = &cli.override_details {
+        requests.push(ControlRequest::Override {
+            details: Some(details.clone()),
+            state: cli.override_state.clone(),
+            timer: cli.override_timer,
+        });
+    }
+    if cli.paused {
+        requests.push(ControlRequest::Pause);
+    }
+    requests.push(ControlRequest::Focus);
+    requests
+}
+
+/// Hands this launch over to an instance already listening on `endpoint`.
+/// Returns `None` when there is none, otherwise a message describing what was forwarded.
+pub async fn forward_launch(endpoint: &str, cli: &Cli) -> Result<Option<String>> {
+    let requests = forwarded_requests(cli);
+    allow_foreground_handoff();
+    let mut sent = Vec::new();
+    for request in &requests {
+        let response = match send(endpoint, request).await {
+            Ok(response) => response,
+            Err(e) if sent.is_empty() && no_instance(&e) => return Ok(None),
+            Err(e) if io_kind(&e) == Some(ErrorKind::PermissionDenied) => bail!(
+                "MotorStormRPC is already running, but as a different user or elevated, so this launch can't reach it at {}",
+                endpoint
+            ),
+            Err(e) => return Err(e.context("MotorStormRPC is already running, but forwarding to it failed")),
+        };
+        if response["ok"] != true {
+            bail!(
+                "MotorStormRPC is already running and rejected the arguments: {}",
+                response["error"].as_str().unwrap_or("request failed")
+            );
+        }
+        sent.push(match request {
+            ControlRequest::Override { details: None, .. } => "clear override",
+            ControlRequest::Override { .. } => "override",
+            ControlRequest::Pause => "pause",
+            _ => "focus",
+        });
+    }
+    Ok(Some(format!("MotorStormRPC is already running at {}. Sent it: {}. Exiting.", endpoint, sent.join(", "))))
+}
+
+/// `--takeover`: asks the instance at `endpoint` to quit and waits until it has
+/// released the endpoint. Used by the elevated relaunch to replace its parent.
+pub async fn take_over(endpoint: &str) -> Result<()> {
+    match send(endpoint, &ControlRequest::Quit).await {
+        Err(e) if no_instance(&e) => return Ok(()),
+        Err(e) => return Err(e.context("Failed to ask the running instance to quit")),
+        Ok(_) => {}
+    }
+    let deadline = Instant::now() + Duration::from_millis(TAKEOVER_TIMEOUT_MS);
+    while Instant::now() < deadline {
+        match send(endpoint, &ControlRequest::Status).await {
+            Err(e) if no_instance(&e) => return Ok(()),
+            _ => sleep(Duration::from_millis(100)).await,
+        }
+    }
+    bail!("The running instance didn't exit within {} ms", TAKEOVER_TIMEOUT_MS)
+}
+
+fn io_kind(e: &anyhow::Error) -> Option<ErrorKind> {
+    e.root_cause().downcast_ref::<std::io::Error>().map(|e| e.kind())
+}
+
+// Nothing listening, or a socket left behind by a crash
+fn no_instance(e: &anyhow::Error) -> bool {
+    matches!(io_kind(e), Some(ErrorKind::NotFound | ErrorKind::ConnectionRefused))
+}
+
+/// `ctl <cmd>`: prints the running instance's response, failing if it reports an error.
+pub async fn run_client(cmd: CtlCommand) -> Result<()> {
+    let response = send(&endpoint()?, &cmd.into()).await?;
+    if response["ok"] != true {
+        bail!("{}", response["error"].as_str().unwrap_or("Request failed"));
+    }
+    println!("{:#}", response);
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn server() -> (tempfile::TempDir, String, Arc<Mutex<AppState>>, Arc<AtomicBool>, ControlServer) {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = dir.path().join("control.sock").display().to_string();
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let server = start(&endpoint, app_state.clone(), running.clone()).unwrap();
+        (dir, endpoint, app_state, running, server)
+    }
+
+    #[tokio::test]
+    async fn commands_drive_the_running_instance() {
+        let (_dir, endpoint, app_state, running, _server) = server();
+
+        let response = send(&endpoint, &ControlRequest::Pause).await.unwrap();
+        assert_eq!(response["ok"], true);
+        assert_eq!(response["status"]["presence_paused"], true);
+        assert!(app_state.lock().presence_paused);
+
+        let request = ControlRequest::Override { details: Some("Practising Lap Times".to_string()), state: None, timer: true };
+        let response = send(&endpoint, &request).await.unwrap();
+        assert_eq!(response["status"]["manual_override"]["details"], "Practising Lap Times");
+
+        let request = ControlRequest::Override { details: Some("x".to_string()), state: None, timer: false };
+        let response = send(&endpoint, &request).await.unwrap();
+        assert_eq!(response["ok"], false);
+        assert!(app_state.lock().manual_override.is_some());
+
+        let request = ControlRequest::Override { details: None, state: None, timer: false };
+        send(&endpoint, &request).await.unwrap();
+        assert!(app_state.lock().manual_override.is_none());
+
+        assert_eq!(send(&endpoint, &ControlRequest::Quit).await.unwrap()["ok"], true);
+        assert!(!running.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn protocol_is_line_delimited_json() {
+        let (_dir, endpoint, _app_state, _running, _server) = server();
+        let stream = tokio::net::UnixStream::connect(&endpoint).await.unwrap();
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        writer.write_all(b"{\"cmd\": \"status\"}\n{\"cmd\": \"fly\"}\n").await.unwrap();
+        let status: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(status["status"]["game_running"], false);
+        let invalid: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(invalid["ok"], false);
+        assert!(invalid["error"].as_str().unwrap().starts_with("Invalid request"));
+    }
+
+    #[tokio::test]
+    async fn stale_sockets_are_replaced_but_live_ones_are_not() {
+        let (dir, endpoint, app_state, running, _server) = server();
+        let err = start(&endpoint, app_state.clone(), running.clone()).err().unwrap();
+        assert!(err.to_string().contains("in use by another instance"));
+
+        // Left behind by an instance that crashed
+        let stale = dir.path().join("stale.sock").display().to_string();
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        let _replacement = start(&stale, app_state, running).unwrap();
+        assert_eq!(send(&stale, &ControlRequest::Status).await.unwrap()["ok"], true);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_launches_get_exactly_one_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Handle::current();
+        for round in 0..20 {
+            let endpoint = dir.path().join(format!("race-{}.sock", round)).display().to_string();
+            let barrier = Arc::new(std::sync::Barrier::new(2));
+            let launches: Vec<_> = (0..2)
+                .map(|_| {
+                    let (endpoint, barrier, runtime) = (endpoint.clone(), barrier.clone(), runtime.clone());
+                    std::thread::spawn(move || {
+                        let _guard = runtime.enter();
+                        barrier.wait();
+                        start(&endpoint, Arc::new(Mutex::new(AppState::new())), Arc::new(AtomicBool::new(true)))
+                    })
+                })
+                .collect();
+            let results: Vec<_> = launches.into_iter().map(|t| t.join().unwrap()).collect();
+
+            let servers: Vec<_> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+            assert_eq!(servers.len(), 1, "round {}", round);
+            let loser = results.iter().find_map(|r| r.as_ref().err()).unwrap();
+            assert!(loser.is::<InUse>(), "round {}: {:#}", round, loser);
+            assert_eq!(send(&endpoint, &ControlRequest::Status).await.unwrap()["ok"], true);
+        }
+    }
+
+    #[tokio::test]
+    async fn socket_is_private_to_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = dir.path().join("run/motorstormrpc/control.sock");
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        let _server = start(&endpoint.display().to_string(), app_state, Arc::new(AtomicBool::new(true))).unwrap();
+
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&endpoint), 0o600);
+        assert_eq!(mode(endpoint.parent().unwrap()), 0o700);
+        // Only the socket is left; the staging directory is gone
+        assert_eq!(std::fs::read_dir(endpoint.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_second_launch_forwards_its_arguments() {
+        let (dir, endpoint, app_state, _running, _server) = server();
+        let cli = Cli {
+            override_details: Some("Practising Lap Times".to_string()),
+            paused: true,
+            ..Cli::default()
+        };
+        let requests = forwarded_requests(&cli);
+        assert!(matches!(requests.last(), Some(ControlRequest::Focus)));
+
+        let message = forward_launch(&endpoint, &cli).await.unwrap().unwrap();
+        assert!(message.contains("Sent it: override, pause, focus"));
+        assert!(app_state.lock().presence_paused);
+        assert_eq!(app_state.lock().manual_override.as_ref().unwrap().details, "Practising Lap Times");
+
+        let invalid = Cli { override_details: Some("x".to_string()), ..Cli::default() };
+        assert!(forward_launch(&endpoint, &invalid).await.unwrap_err().to_string().contains("rejected the arguments"));
+        let clear = Cli { clear_override: true, ..Cli::default() };
+        forward_launch(&endpoint, &clear).await.unwrap();
+        assert!(app_state.lock().manual_override.is_none());
+
+        let nobody = dir.path().join("nobody.sock").display().to_string();
+        assert!(forward_launch(&nobody, &Cli::default()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn takeover_waits_for_the_old_instance_to_exit() {
+        let (dir, endpoint, _app_state, running, server) = server();
+        // Stands in for main() returning once the TUI loop sees `running` cleared
+        tokio::spawn(async move {
+            while running.load(Ordering::Relaxed) {
+                sleep(Duration::from_millis(10)).await;
+            }
+            sleep(Duration::from_millis(200)).await;
+            drop(server);
+        });
+
+        take_over(&endpoint).await.unwrap();
+        assert!(!std::path::Path::new(&endpoint).exists());
+        let nobody = dir.path().join("nobody.sock").display().to_string();
+        take_over(&nobody).await.unwrap();
+    }
+}
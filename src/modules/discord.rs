@@ -1,88 +1,363 @@
-use crate::modules::config::{CLIENT_ID, GAME_NAME};
+use crate::modules::config::{GAME_NAME, LARGE_IMAGE_KEY, SMALL_IMAGE_KEY};
+use crate::modules::ipc::{
+    Activity, Assets, ClientFlavor, DiscordUser, Endpoint, IpcConnection, IpcError, Timestamps,
+};
 
-use anyhow::Result;
+/// Text and timer of an activity to show on Discord.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Presence {
+    pub details: String,
+    pub state: Option<String>,
+    pub start_time: Option<i64>,
+}
 
-use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+impl Presence {
+    pub fn to_activity(&self) -> Activity {
+        // NOTE: If the Application ID does not have these specific assets uploaded in the Discord Developer Portal,
+        // the Rich Presence might NOT appear at all.
+        // For safety, we will try to set it, but if it fails silently (Discord side), it might be due to missing assets.
+        Activity {
+            details: Some(self.details.clone()),
+            state: self.state.clone(),
+            timestamps: self.start_time.map(|start| Timestamps { start: Some(start) }),
+            assets: Some(Assets {
+                large_image: Some(LARGE_IMAGE_KEY.to_string()), // Ensure this key exists in Developer Portal!
+                large_text: Some(GAME_NAME.to_string()),
+                small_image: Some(SMALL_IMAGE_KEY.to_string()), // Ensure this key exists! (`--check-assets` verifies both)
+                small_text: Some("RPCS3 Emulator".to_string()),
+            }),
+        }
+    }
+}
 
 pub struct DiscordClient {
-    client: Option<DiscordIpcClient>,
-    is_connected: bool,
+    client_id: String,
+    preferred_endpoint: Option<String>,
+    connection: Option<IpcConnection>,
+    paused: bool,
 }
 
 impl DiscordClient {
-    pub fn new() -> Self {
+    pub fn new(client_id: &str) -> Self {
         Self {
-            client: DiscordIpcClient::new(CLIENT_ID).ok(),
-            is_connected: false,
+            client_id: client_id.to_string(),
+            preferred_endpoint: None,
+            connection: None,
+            paused: false,
         }
     }
 
-    pub fn connect(&mut self) -> Result<()> {
-        if self.is_connected {
-            return Ok(());
+    /// Switches to another application ID; the next `connect` uses the new ID.
+    pub fn set_client_id(&mut self, client_id: &str) {
+        if let Some(connection) = self.connection.take() {
+            connection.close();
         }
+        self.client_id = client_id.to_string();
+    }
 
-        if let Some(client) = &mut self.client {
-            client.connect()
-                .map_err(|e| anyhow::anyhow!("Failed to connect to Discord: {}", e))?;
-            self.is_connected = true;
-        } else {
-             // Try re-creating if it failed initially (rare)
-             self.client = DiscordIpcClient::new(CLIENT_ID).ok();
-             if let Some(client) = &mut self.client {
-                 client.connect()
-                     .map_err(|e| anyhow::anyhow!("Failed to connect to Discord after recreate: {}", e))?;
-                 self.is_connected = true;
-             }
+    /// Pins the IPC endpoint to connect to; `None` uses the first one found.
+    /// Takes effect on the next `connect`.
+    pub fn set_preferred_endpoint(&mut self, path: Option<&str>) {
+        let path = path.map(str::to_string);
+        if path != self.preferred_endpoint {
+            if let Some(connection) = self.connection.take() {
+                connection.close();
+            }
+            self.preferred_endpoint = path;
         }
-        Ok(())
     }
 
-    pub fn update_presence(&mut self, start_time: i64) -> Result<()> {
-        if !self.is_connected {
+    pub fn connect(&mut self) -> Result<(), IpcError> {
+        if self.connection.is_some() {
             return Ok(());
         }
-
-        if let Some(client) = &mut self.client {
-             let details = format!("Playing {}", GAME_NAME);
-            // NOTE: If the Application ID does not have these specific assets uploaded in the Discord Developer Portal,
-            // the Rich Presence might NOT appear at all.
-            // For safety, we will try to set it, but if it fails silently (Discord side), it might be due to missing assets.
-            // We use standard keys often present or fallback.
-            
-            let assets = activity::Assets::new()
-                .large_image("motorstorm") // Ensure this key exists in Developer Portal!
-                .large_text(GAME_NAME)
-                .small_image("rpcs3")      // Ensure this key exists!
-                .small_text("RPCS3 Emulator");
-
-            let timestamps = activity::Timestamps::new().start(start_time);
-
-            let payload = activity::Activity::new()
-                .details(&details)
-                .state("On RPCS3 Emulator")
-                .assets(assets)
-                .timestamps(timestamps);
-
-            client.set_activity(payload)
-                .map_err(|e| anyhow::anyhow!("Failed to set activity: {}", e))?;
-             // We can optionally return true/false or something to indicate success if we want generic logging,
-             // but caller handles error.
-        }
+        self.connection = Some(IpcConnection::connect(&self.client_id, self.preferred_endpoint.as_deref())?);
         Ok(())
     }
 
-    pub fn clear_presence(&mut self) -> Result<()> {
-        if !self.is_connected {
+    /// Withholds further updates until `resume` and clears the current activity.
+    /// Stays paused even if the clear fails, so nothing is broadcast either way.
+    pub fn pause(&mut self) -> Result<(), IpcError> {
+        self.paused = true;
+        self.clear_presence()
+    }
+
+    /// Allows updates again; the caller re-sends the current activity.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn update_presence(&mut self, presence: &Presence) -> Result<(), IpcError> {
+        if self.paused {
             return Ok(());
         }
-        if let Some(client) = &mut self.client {
-            let _ = client.clear_activity();
+        self.send(Some(&presence.to_activity()))
+    }
+
+    pub fn clear_presence(&mut self) -> Result<(), IpcError> {
+        self.send(None)
+    }
+
+    fn send(&mut self, activity: Option<&Activity>) -> Result<(), IpcError> {
+        let Some(connection) = &mut self.connection else {
+            return Ok(());
+        };
+        match connection.set_activity(activity) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // A broken pipe or CLOSE frame leaves nothing to talk to
+                if e.is_fatal() {
+                    self.connection = None;
+                }
+                Err(e)
+            }
         }
-        Ok(())
     }
 
     pub fn is_connected(&self) -> bool {
-        self.is_connected
+        self.connection.is_some()
+    }
+
+    /// The Discord account from the READY handshake, while connected.
+    pub fn user(&self) -> Option<&DiscordUser> {
+        self.connection.as_ref().map(|c| c.user())
+    }
+
+    /// The IPC endpoint in use, while connected.
+    pub fn endpoint(&self) -> Option<&Endpoint> {
+        self.connection.as_ref().map(|c| c.endpoint())
+    }
+
+    /// Release channel of the connected client, while connected.
+    pub fn flavor(&self) -> Option<ClientFlavor> {
+        self.connection.as_ref().map(|c| c.flavor())
+    }
+}
+
+impl Drop for DiscordClient {
+    // Clear on exit so the activity doesn't linger until Discord notices the closed socket
+    fn drop(&mut self) {
+        if let Some(mut connection) = self.connection.take() {
+            let _ = connection.set_activity(None);
+            connection.close();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::modules::ipc::{discover_endpoints, Packaging};
+    use crate::modules::mock_ipc::{Fault, MockDiscord};
+    use std::time::Duration;
+
+    const CLIENT_ID: &str = "1164946062499389470";
+    const WAIT: Duration = Duration::from_secs(2);
+
+    fn presence(details: &str) -> Presence {
+        Presence {
+            details: details.to_string(),
+            state: Some("On RPCS3 Emulator".to_string()),
+            start_time: Some(1_700_000_000),
+        }
+    }
+
+    #[test]
+    fn connect_reports_user_and_endpoint() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+
+        assert!(client.is_connected());
+        assert_eq!(client.user().unwrap().display_name(), "Mock Racer");
+        assert_eq!(client.endpoint().unwrap().path, mock.socket().to_str().unwrap());
+        assert_eq!(client.endpoint().unwrap().packaging, Packaging::Native);
+        assert_eq!(client.flavor(), Some(ClientFlavor::Stable));
+        assert_eq!(mock.handshakes(), vec![CLIENT_ID.to_string()]);
+    }
+
+    #[test]
+    fn update_presence_sends_activity_payload() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        client.update_presence(&presence("Playing MotorStorm")).unwrap();
+
+        let activities = mock.wait_for_activities(1, WAIT);
+        assert_eq!(activities[0]["details"], "Playing MotorStorm");
+        assert_eq!(activities[0]["state"], "On RPCS3 Emulator");
+        assert_eq!(activities[0]["timestamps"]["start"], 1_700_000_000);
+        assert_eq!(activities[0]["assets"]["large_image"], "motorstorm");
+    }
+
+    #[test]
+    fn paused_client_clears_and_withholds_updates() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        client.update_presence(&presence("Before pause")).unwrap();
+        client.pause().unwrap();
+        client.update_presence(&presence("While paused")).unwrap();
+        client.resume();
+        client.update_presence(&presence("After resume")).unwrap();
+
+        let activities = mock.wait_for_activities(3, WAIT);
+        assert_eq!(activities.len(), 3);
+        assert_eq!(activities[0]["details"], "Before pause");
+        assert!(activities[1].is_null());
+        assert_eq!(activities[2]["details"], "After resume");
+    }
+
+    #[test]
+    fn dropping_client_clears_presence() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        client.update_presence(&presence("Racing")).unwrap();
+        drop(client);
+
+        let activities = mock.wait_for_activities(2, WAIT);
+        assert!(activities.last().unwrap().is_null());
+    }
+
+    #[test]
+    fn invalid_client_id_is_distinguished() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        mock.inject(Fault::Close { code: 4000, message: "Invalid Client ID".to_string() });
+
+        let mut client = DiscordClient::new("123");
+        match client.connect() {
+            Err(IpcError::InvalidClientId(msg)) => assert_eq!(msg, "Invalid Client ID"),
+            other => panic!("expected InvalidClientId, got {:?}", other.err()),
+        }
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn version_mismatch_is_not_reported_as_a_bad_client_id() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        mock.inject(Fault::Close { code: 4004, message: "Invalid version".to_string() });
+        mock.inject(Fault::Error { code: 4004, message: "Invalid version".to_string() });
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        for _ in 0..2 {
+            match client.connect() {
+                Err(e @ IpcError::UnsupportedVersion(_)) => assert!(e.to_string().contains("(4004): Invalid version")),
+                other => panic!("expected UnsupportedVersion, got {:?}", other.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn missing_socket_reports_not_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = crate::modules::mock_ipc::use_runtime_dir(dir.path());
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        assert!(matches!(client.connect(), Err(IpcError::NotRunning)));
+    }
+
+    #[test]
+    fn discord_error_response_is_surfaced() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        mock.inject(Fault::Error { code: 4002, message: "child \"activity\" fails".to_string() });
+
+        match client.update_presence(&presence("Racing")) {
+            Err(IpcError::Discord { code, .. }) => assert_eq!(code, 4002),
+            other => panic!("expected Discord error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn client_id_change_reconnects_with_new_id() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        client.set_client_id("1164946062499389471");
+        assert!(!client.is_connected());
+        client.connect().unwrap();
+        client.update_presence(&presence("Racing")).unwrap();
+
+        assert_eq!(mock.handshakes(), vec![CLIENT_ID.to_string(), "1164946062499389471".to_string()]);
+        assert_eq!(mock.wait_for_activities(1, WAIT).len(), 1);
+    }
+
+    #[test]
+    fn disconnect_surfaces_as_error_and_marks_disconnected() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        mock.inject(Fault::Disconnect);
+
+        assert!(client.update_presence(&presence("Racing")).is_err());
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn discovers_sandboxed_endpoints_and_flavor() {
+        let mock = MockDiscord::start_in("app/com.discordapp.Discord", 0);
+        mock.set_api_endpoint("//canary.discord.com/api");
+        let _env = mock.activate();
+
+        let found = discover_endpoints();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].packaging, Packaging::Flatpak);
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.connect().unwrap();
+        assert_eq!(client.endpoint().unwrap().packaging, Packaging::Flatpak);
+        assert_eq!(client.flavor(), Some(ClientFlavor::Canary));
+    }
+
+    #[test]
+    fn preferred_endpoint_is_used_over_first_found() {
+        let first = MockDiscord::start_at(0);
+        let second = first.sibling(3);
+        let _env = first.activate();
+
+        assert_eq!(discover_endpoints().len(), 2);
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        client.set_preferred_endpoint(second.socket().to_str());
+        client.connect().unwrap();
+
+        assert_eq!(client.endpoint().unwrap().path, second.socket().to_str().unwrap());
+        assert!(first.handshakes().is_empty());
+        assert_eq!(second.handshakes().len(), 1);
+    }
+
+    #[test]
+    fn missing_preferred_endpoint_does_not_fall_back() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+
+        let mut client = DiscordClient::new(CLIENT_ID);
+        let missing = mock.dir().join("discord-ipc-9");
+        client.set_preferred_endpoint(missing.to_str());
+
+        assert!(matches!(client.connect(), Err(IpcError::NotRunning)));
+        assert!(mock.handshakes().is_empty());
     }
 }
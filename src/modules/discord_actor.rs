@@ -0,0 +1,311 @@
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::{ACTOR_TICK_MS, SHUTDOWN_TIMEOUT_MS};
+use crate::modules::connection::{ConnectionEvent, ConnectionManager, LastError, SentActivity, UpdateStats};
+use crate::modules::discord::{DiscordClient, Presence};
+use crate::modules::ipc::{discover_endpoints, ClientFlavor, DiscordUser, Endpoint, IpcError};
+use parking_lot::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Requests handled by the Discord actor, in order.
+#[derive(Debug)]
+pub enum DiscordCommand {
+    /// Show this activity, or clear it when `None`.
+    SetActivity(Option<Presence>),
+    Pause,
+    Resume,
+    SetClientId(String),
+    SetPreferredEndpoint(Option<String>),
+    /// At most `max` activity updates per `window`.
+    SetRateLimit { max: u32, window: Duration },
+    /// Clear the activity, close the connection and stop.
+    Shutdown,
+}
+
+/// Connection state published by the actor after every change.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiscordStatus {
+    pub connected: bool,
+    pub paused: bool,
+    pub user: Option<DiscordUser>,
+    pub endpoint: Option<Endpoint>,
+    pub flavor: Option<ClientFlavor>,
+    pub last_activity: Option<SentActivity>,
+    pub last_error: Option<LastError>,
+    pub updates: UpdateStats,
+}
+
+/// Cheap, cloneable handle for talking to the actor.
+#[derive(Clone)]
+pub struct DiscordHandle {
+    commands: Sender<DiscordCommand>,
+    status: watch::Receiver<DiscordStatus>,
+}
+
+impl DiscordHandle {
+    /// Queues a command; never blocks, even if Discord I/O is stuck.
+    pub fn send(&self, command: DiscordCommand) {
+        // The actor only goes away on shutdown, when nobody cares anymore
+        let _ = self.commands.send(command);
+    }
+
+    pub fn status(&self) -> DiscordStatus {
+        self.status.borrow().clone()
+    }
+}
+
+/// Starts the actor on a blocking-friendly task, so a hung socket can stall
+/// neither process scanning nor the UI.
+pub fn spawn(client: DiscordClient, app_state: Arc<Mutex<AppState>>) -> (DiscordHandle, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel();
+    let (status_tx, status_rx) = watch::channel(DiscordStatus::default());
+    let task = tokio::task::spawn_blocking(move || run(client, rx, status_tx, app_state));
+    (DiscordHandle { commands: tx, status: status_rx }, task)
+}
+
+/// Asks the actor to clear the activity and waits briefly for it to finish.
+pub async fn shutdown(handle: &DiscordHandle, task: JoinHandle<()>) {
+    handle.send(DiscordCommand::Shutdown);
+    let _ = tokio::time::timeout(Duration::from_millis(SHUTDOWN_TIMEOUT_MS), task).await;
+}
+
+fn run(
+    client: DiscordClient,
+    commands: Receiver<DiscordCommand>,
+    status: watch::Sender<DiscordStatus>,
+    app_state: Arc<Mutex<AppState>>,
+) {
+    let mut manager = ConnectionManager::new(client);
+    {
+        let settings = &app_state.lock().settings;
+        manager.set_rate_limit(settings.activity_rate_limit, Duration::from_millis(settings.activity_rate_window_ms));
+    }
+    let mut last_connect_error: Option<String> = None;
+    let tick = Duration::from_millis(ACTOR_TICK_MS);
+
+    loop {
+        let mut events = Vec::new();
+        // Only the last activity of a burst matters
+        let mut activity: Option<Option<Presence>> = None;
+        let mut next = commands.recv_timeout(tick);
+
+        // Drain everything queued so bursts are handled in one pass
+        loop {
+            let command = match next {
+                Ok(command) => command,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            match command {
+                DiscordCommand::SetActivity(presence) => activity = Some(presence),
+                DiscordCommand::Pause => {
+                    if let Err(e) = manager.pause() {
+                        app_state.lock().add_log(LogLevel::Error, format!("Failed to clear presence, privacy mode stays on and the clear is retried: {}", e));
+                    }
+                }
+                DiscordCommand::Resume => {
+                    if let Some(presence) = activity.take() {
+                        events.extend(manager.set_activity(presence, Instant::now()));
+                    }
+                    events.extend(manager.resume(Instant::now()));
+                }
+                DiscordCommand::SetClientId(id) => {
+                    manager.client_mut().set_client_id(&id);
+                    manager.reconnect_now();
+                }
+                DiscordCommand::SetPreferredEndpoint(path) => {
+                    manager.client_mut().set_preferred_endpoint(path.as_deref());
+                    manager.reconnect_now();
+                }
+                DiscordCommand::SetRateLimit { max, window } => manager.set_rate_limit(max, window),
+                DiscordCommand::Shutdown => {
+                    // Dropping the client clears the activity and closes the socket
+                    drop(manager);
+                    status.send_replace(DiscordStatus::default());
+                    return;
+                }
+            }
+            next = commands.try_recv().map_err(|e| match e {
+                mpsc::TryRecvError::Empty => RecvTimeoutError::Timeout,
+                mpsc::TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+            });
+        }
+
+        if let Some(presence) = activity {
+            events.extend(manager.set_activity(presence, Instant::now()));
+        }
+        events.extend(manager.tick(Instant::now()));
+        if !events.is_empty() {
+            let debug_mode = app_state.lock().debug_mode;
+            log_connection_events(&app_state, &manager, events, debug_mode, &mut last_connect_error);
+        }
+
+        let client = manager.client();
+        status.send_if_modified(|current| {
+            let updated = DiscordStatus {
+                connected: manager.is_connected(),
+                paused: client.is_paused(),
+                user: client.user().cloned(),
+                endpoint: client.endpoint().cloned(),
+                flavor: client.flavor(),
+                last_activity: manager.last_activity().cloned(),
+                last_error: manager.last_error().cloned(),
+                updates: manager.stats(),
+            };
+            let changed = *current != updated;
+            *current = updated;
+            changed
+        });
+    }
+}
+
+/// Writes connection manager events to the log; repeated connect failures are only reported once.
+fn log_connection_events(
+    app_state: &Arc<Mutex<AppState>>,
+    manager: &ConnectionManager,
+    events: Vec<ConnectionEvent>,
+    debug_mode: bool,
+    last_connect_error: &mut Option<String>,
+) {
+    let mut state = app_state.lock();
+    for event in events {
+        match event {
+            ConnectionEvent::Connected { resent, elapsed } => {
+                let discord = manager.client();
+                let name = discord.user().map(|u| u.display_name().to_string()).unwrap_or_default();
+                let flavor = discord.flavor().map(|f| f.to_string()).unwrap_or_default();
+                let (path, packaging) = discord
+                    .endpoint()
+                    .map(|e| (e.path.clone(), e.packaging.to_string()))
+                    .unwrap_or_default();
+                state.add_log(LogLevel::Success, format!("Connected to {} ({}) as {} via {}", flavor, packaging, name, path));
+                if debug_mode {
+                    state.add_log(LogLevel::Info, format!("[debug] Discord handshake round-trip: {:?}", elapsed));
+                }
+                if resent {
+                    state.add_log(LogLevel::Info, "Restoring current activity after reconnect".to_string());
+                }
+                let others = discover_endpoints().len().saturating_sub(1);
+                if others > 0 && state.settings.preferred_endpoint.is_empty() {
+                    state.add_log(LogLevel::Info, format!(
+                        "{} other Discord endpoint(s) found; pin one with \"Preferred endpoint\" in settings",
+                        others
+                    ));
+                }
+                *last_connect_error = None;
+            }
+            ConnectionEvent::ConnectFailed { error, retry_in, elapsed } => {
+                let msg = error.to_string();
+                if last_connect_error.as_deref() != Some(msg.as_str()) {
+                    let level = match error {
+                        IpcError::NotRunning => LogLevel::Warning,
+                        _ => LogLevel::Error,
+                    };
+                    state.add_log(level, msg.clone());
+                    *last_connect_error = Some(msg);
+                }
+                if debug_mode {
+                    state.add_log(LogLevel::Info, format!(
+                        "[debug] Discord connect failed after {:?}, retrying in {:.1}s",
+                        elapsed,
+                        retry_in.as_secs_f32()
+                    ));
+                }
+            }
+            ConnectionEvent::Disconnected { error, retry_in } => {
+                state.add_log(LogLevel::Warning, format!(
+                    "Lost connection to Discord ({}), reconnecting in {:.1}s",
+                    error,
+                    retry_in.as_secs_f32()
+                ));
+            }
+            ConnectionEvent::ActivitySent { cleared, elapsed } => {
+                if debug_mode {
+                    let cmd = if cleared { "CLEAR_ACTIVITY" } else { "SET_ACTIVITY" };
+                    let stats = manager.stats();
+                    state.add_log(LogLevel::Info, format!(
+                        "[debug] Discord {} round-trip: {:?} ({} sent, {} suppressed)",
+                        cmd, elapsed, stats.sent, stats.suppressed
+                    ));
+                }
+            }
+            ConnectionEvent::ActivityDeferred { retry_in } => {
+                if debug_mode {
+                    state.add_log(LogLevel::Info, format!(
+                        "[debug] Activity update rate-limited, sending the latest in {:.1}s",
+                        retry_in.as_secs_f32()
+                    ));
+                }
+            }
+            ConnectionEvent::ActivityRejected { error } => {
+                state.add_log(LogLevel::Error, format!("Discord rejected the activity: {}", error));
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::modules::mock_ipc::MockDiscord;
+
+    const CLIENT_ID: &str = "1164946062499389470";
+    const WAIT: Duration = Duration::from_secs(3);
+
+    async fn wait_connected(handle: &DiscordHandle) {
+        let deadline = Instant::now() + WAIT;
+        while !handle.status().connected && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(handle.status().connected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn actor_sends_activity_and_clears_on_shutdown() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+
+        let (handle, task) = spawn(DiscordClient::new(CLIENT_ID), app_state.clone());
+        wait_connected(&handle).await;
+        assert_eq!(handle.status().user.unwrap().username, "mockracer");
+
+        handle.send(DiscordCommand::SetActivity(Some(Presence {
+            details: "Playing MotorStorm".to_string(),
+            state: None,
+            start_time: None,
+        })));
+        assert_eq!(mock.wait_for_activities(1, WAIT)[0]["details"], "Playing MotorStorm");
+
+        shutdown(&handle, task).await;
+        let activities = mock.wait_for_activities(2, WAIT);
+        assert!(activities.last().unwrap().is_null());
+        assert!(!handle.status().connected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pause_and_resume_via_commands() {
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+
+        let (handle, task) = spawn(DiscordClient::new(CLIENT_ID), app_state);
+        wait_connected(&handle).await;
+
+        let presence = Presence { details: "Racing".to_string(), state: None, start_time: None };
+        handle.send(DiscordCommand::SetActivity(Some(presence)));
+        mock.wait_for_activities(1, WAIT);
+        handle.send(DiscordCommand::Pause);
+        handle.send(DiscordCommand::Resume);
+
+        let activities = mock.wait_for_activities(3, WAIT);
+        assert_eq!(activities[0]["details"], "Racing");
+        assert!(activities[1].is_null());
+        assert_eq!(activities[2]["details"], "Racing");
+
+        shutdown(&handle, task).await;
+    }
+}
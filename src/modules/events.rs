@@ -0,0 +1,109 @@
+//! Events published as things happen, for overlays and integrations.
+//!
+//! Every event serializes to one JSON object with an `event` name and the
+//! Unix timestamp `at` it happened, plus the fields of its kind:
+//!
+//! | `event` | Fields |
+//! | :--- | :--- |
+//! | `session_started` | `window_title`, `start`, `pid` |
+//! | `session_stopped` | `window_title`, `start`, `end`, `duration_secs`, `pid` |
+//! | `game_changed` | `window_title` |
+//! | `presence_paused` / `presence_resumed` | |
+//! | `discord_connected` | `user`, `endpoint` |
+//! | `discord_disconnected` | |
+//! | `log` | `seq`, `level`, `message` |
+
+use crate::modules::app::AppState;
+use crate::modules::config::GAME_NAME;
+use crate::modules::process::title_id;
+use crate::modules::utils::format_duration;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+// Subscribers further behind than this skip ahead
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    SessionStarted { window_title: Option<String>, start: i64, pid: Option<u32> },
+    SessionStopped { window_title: Option<String>, start: i64, end: i64, duration_secs: i64, pid: Option<u32> },
+    /// The matched game window changed while a session is running.
+    GameChanged { window_title: Option<String> },
+    PresencePaused,
+    PresenceResumed,
+    DiscordConnected { user: Option<String>, endpoint: Option<String> },
+    DiscordDisconnected,
+    Log { seq: u64, level: &'static str, message: String },
+}
+
+impl EventKind {
+    /// The `event` name this kind serializes with.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::SessionStarted { .. } => "session_started",
+            EventKind::SessionStopped { .. } => "session_stopped",
+            EventKind::GameChanged { .. } => "game_changed",
+            EventKind::PresencePaused => "presence_paused",
+            EventKind::PresenceResumed => "presence_resumed",
+            EventKind::DiscordConnected { .. } => "discord_connected",
+            EventKind::DiscordDisconnected => "discord_disconnected",
+            EventKind::Log { .. } => "log",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AppEvent {
+    pub at: i64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl AppEvent {
+    /// Values describing the event for webhook bodies and hook environments, as
+    /// `(name, value)`. Session events carry their own window and pid; other events
+    /// use the current state. `duration_secs` and `pid` are numbers, `0` when unknown.
+    pub fn vars(&self, state: &AppState) -> Vec<(&'static str, String)> {
+        let (window, duration_secs, pid) = match &self.kind {
+            EventKind::SessionStarted { window_title, pid, .. } => (window_title.clone(), 0, *pid),
+            EventKind::SessionStopped { window_title, duration_secs, pid, .. } => {
+                (window_title.clone(), *duration_secs, *pid)
+            }
+            EventKind::GameChanged { window_title } => (window_title.clone(), 0, state.game_pid),
+            _ => (state.matched_window.clone(), 0, state.game_pid),
+        };
+        vec![
+            ("event", self.kind.name().to_string()),
+            ("game", window.clone().unwrap_or_else(|| GAME_NAME.to_string())),
+            ("title_id", window.as_deref().and_then(title_id).unwrap_or_default()),
+            ("pid", pid.unwrap_or(0).to_string()),
+            ("user", state.discord.user.as_ref().map(|u| u.display_name().to_string()).unwrap_or_default()),
+            ("duration", if duration_secs > 0 { format_duration(duration_secs) } else { String::new() }),
+            ("duration_secs", duration_secs.to_string()),
+            ("at", self.at.to_string()),
+        ]
+    }
+}
+
+/// Fan-out of `AppEvent`s to any number of subscribers; publishing never blocks.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<AppEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self { sender: broadcast::channel(EVENT_BUFFER).0 }
+    }
+
+    pub fn publish(&self, kind: EventKind) {
+        // Nobody listening is fine
+        let _ = self.sender.send(AppEvent { at: Utc::now().timestamp(), kind });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,188 @@
+//! User script hooks: shell commands run on session events.
+//!
+//! Each command gets the event's variables (see `AppEvent::vars`) as
+//! `MOTORSTORM_*` environment variables, e.g. `MOTORSTORM_TITLE_ID`. Commands
+//! run concurrently in their own tasks, are killed after their timeout, and
+//! their output is copied into the log.
+
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::HookCommand;
+use crate::modules::events::EventKind;
+use parking_lot::Mutex;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout, Duration};
+
+// Lines of stdout and of stderr copied into the log per run
+const OUTPUT_LINES: usize = 20;
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// Runs `hook` with `env`, returning log entries for its output and result.
+async fn run_hook(hook: &HookCommand, env: Vec<(String, String)>) -> Vec<(LogLevel, String)> {
+    let child = shell(&hook.command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // A timed-out run is dropped, which kills it
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => return vec![(LogLevel::Error, format!("Hook `{}`: failed to start: {}", hook.command, e))],
+    };
+
+    let output = match timeout(Duration::from_millis(hook.timeout_ms), child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return vec![(LogLevel::Error, format!("Hook `{}`: {}", hook.command, e))],
+        Err(_) => {
+            return vec![(LogLevel::Error, format!("Hook `{}`: timed out after {} ms, killed", hook.command, hook.timeout_ms))]
+        }
+    };
+
+    let mut entries = Vec::new();
+    for (stream, level) in [(&output.stdout, LogLevel::Info), (&output.stderr, LogLevel::Warning)] {
+        let text = String::from_utf8_lossy(stream);
+        let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+        for line in lines.iter().take(OUTPUT_LINES) {
+            entries.push((level.clone(), format!("[hook] {}", line)));
+        }
+        if lines.len() > OUTPUT_LINES {
+            entries.push((level, format!("[hook] ... {} more line(s)", lines.len() - OUTPUT_LINES)));
+        }
+    }
+    entries.push(match output.status.code() {
+        Some(0) => (LogLevel::Success, format!("Hook `{}`: done", hook.command)),
+        Some(code) => (LogLevel::Warning, format!("Hook `{}`: exited with {}", hook.command, code)),
+        None => (LogLevel::Warning, format!("Hook `{}`: terminated by a signal", hook.command)),
+    });
+    entries
+}
+
+/// Runs the configured hooks for each event until the event bus closes.
+pub async fn run_hooks(app_state: Arc<Mutex<AppState>>) {
+    let mut events = app_state.lock().events.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                app_state.lock().add_log(LogLevel::Warning, format!("Hooks fell behind, {} event(s) skipped", skipped));
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // Hook output is logged, so log events would feed back into hooks
+        if matches!(event.kind, EventKind::Log { .. }) {
+            continue;
+        }
+
+        let (hooks, env): (Vec<HookCommand>, Vec<(String, String)>) = {
+            let state = app_state.lock();
+            let hooks = state
+                .settings
+                .hooks
+                .iter()
+                .filter(|h| h.events.iter().any(|e| e == event.kind.name()))
+                .cloned()
+                .collect();
+            let env = event
+                .vars(&state)
+                .into_iter()
+                .map(|(name, value)| (format!("MOTORSTORM_{}", name.to_uppercase()), value))
+                .collect();
+            (hooks, env)
+        };
+
+        for hook in hooks {
+            let (app_state, env) = (app_state.clone(), env.clone());
+            tokio::spawn(async move {
+                let entries = run_hook(&hook, env).await;
+                let mut state = app_state.lock();
+                for (level, message) in entries {
+                    state.add_log(level, message);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    fn hook(command: &str, events: &[&str], timeout_ms: u64) -> HookCommand {
+        HookCommand {
+            command: command.to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+            timeout_ms,
+        }
+    }
+
+    async fn wait_for_log(app_state: &Arc<Mutex<AppState>>, needle: &str) -> Option<LogLevel> {
+        for _ in 0..300 {
+            if let Some(log) = app_state.lock().logs.iter().find(|l| l.message.contains(needle)) {
+                return Some(log.level.clone());
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        None
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn matching_hooks_get_the_event_and_their_output_is_logged() {
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        app_state.lock().settings.hooks = vec![
+            hook(
+                "echo \"ended $MOTORSTORM_TITLE_ID pid=$MOTORSTORM_PID after $MOTORSTORM_DURATION_SECS\"; echo oops >&2",
+                &["session_stopped"],
+                5000,
+            ),
+            hook("echo paused-hook-ran", &["presence_paused"], 5000),
+        ];
+        tokio::spawn(run_hooks(app_state.clone()));
+        sleep(Duration::from_millis(50)).await;
+
+        app_state.lock().events.publish(EventKind::SessionStopped {
+            window_title: Some("MotorStorm: Pacific Rift [BCES00129]".to_string()),
+            start: 0,
+            end: 65,
+            duration_secs: 65,
+            pid: Some(4242),
+        });
+
+        assert!(matches!(wait_for_log(&app_state, "[hook] ended BCES00129 pid=4242 after 65").await, Some(LogLevel::Info)));
+        assert!(matches!(wait_for_log(&app_state, "[hook] oops").await, Some(LogLevel::Warning)));
+        assert!(matches!(wait_for_log(&app_state, ": done").await, Some(LogLevel::Success)));
+        assert!(!app_state.lock().logs.iter().any(|l| l.message.contains("paused-hook-ran")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn slow_hooks_are_killed_and_failures_reported() {
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        app_state.lock().settings.hooks =
+            vec![hook("sleep 5", &["presence_paused"], 100), hook("exit 3", &["presence_paused"], 5000)];
+        tokio::spawn(run_hooks(app_state.clone()));
+        sleep(Duration::from_millis(50)).await;
+
+        app_state.lock().set_paused(true);
+
+        assert!(matches!(wait_for_log(&app_state, "Hook `sleep 5`: timed out after 100 ms").await, Some(LogLevel::Error)));
+        assert!(matches!(wait_for_log(&app_state, "Hook `exit 3`: exited with 3").await, Some(LogLevel::Warning)));
+    }
+}
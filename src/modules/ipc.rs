@@ -0,0 +1,515 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub const IPC_TIMEOUT_MS: u64 = 5000;
+const MAX_FRAME_LEN: u32 = 1024 * 1024;
+const PIPE_COUNT: u8 = 10;
+
+// Close codes sent by Discord during the handshake
+const CLOSE_INVALID_CLIENT_ID: i64 = 4000;
+const CLOSE_INVALID_VERSION: i64 = 4004;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Handshake = 0,
+    Frame = 1,
+    Close = 2,
+    Ping = 3,
+    Pong = 4,
+}
+
+impl Opcode {
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Opcode::Handshake),
+            1 => Some(Opcode::Frame),
+            2 => Some(Opcode::Close),
+            3 => Some(Opcode::Ping),
+            4 => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IpcError {
+    /// No Discord IPC endpoint accepted the connection.
+    NotRunning,
+    /// Discord rejected the application ID during the handshake.
+    InvalidClientId(String),
+    /// Discord doesn't speak the IPC protocol version we asked for.
+    UnsupportedVersion(String),
+    /// Discord closed the connection with a CLOSE frame.
+    Closed { code: i64, message: String },
+    /// Discord answered a command with an ERROR event.
+    Discord { code: i64, message: String },
+    /// Discord did not answer in time.
+    Timeout,
+    /// Malformed or unexpected frame.
+    Protocol(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::NotRunning => write!(f, "Discord is not running (no IPC endpoint found)"),
+            IpcError::InvalidClientId(msg) => write!(f, "Invalid client ID: {}", msg),
+            IpcError::UnsupportedVersion(msg) => {
+                write!(f, "Discord rejected IPC protocol version 1 ({}): {}", CLOSE_INVALID_VERSION, msg)
+            }
+            IpcError::Closed { code, message } => write!(f, "Discord closed the connection ({}): {}", code, message),
+            IpcError::Discord { code, message } => write!(f, "Discord error {}: {}", code, message),
+            IpcError::Timeout => write!(f, "Timed out waiting for Discord"),
+            IpcError::Protocol(msg) => write!(f, "IPC protocol error: {}", msg),
+            IpcError::Io(e) => write!(f, "IPC I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {}
+
+impl IpcError {
+    /// Whether the connection is unusable after this error and must be re-established.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, IpcError::Discord { .. })
+    }
+}
+
+impl From<io::Error> for IpcError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => IpcError::Timeout,
+            _ => IpcError::Io(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for IpcError {
+    fn from(e: serde_json::Error) -> Self {
+        IpcError::Protocol(format!("invalid JSON: {}", e))
+    }
+}
+
+/// The Discord account the client is connected as, from the READY event.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DiscordUser {
+    pub id: String,
+    pub username: String,
+    #[serde(default)]
+    pub global_name: Option<String>,
+    #[serde(default)]
+    pub discriminator: Option<String>,
+}
+
+impl DiscordUser {
+    pub fn display_name(&self) -> &str {
+        self.global_name.as_deref().unwrap_or(&self.username)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Timestamps {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Assets {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_text: Option<String>,
+}
+
+/// Activity payload for SET_ACTIVITY.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Activity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<Timestamps>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assets: Option<Assets>,
+}
+
+trait IpcStream: Read + Write + Send {}
+impl<T: Read + Write + Send> IpcStream for T {}
+
+pub fn write_frame(w: &mut impl Write, opcode: Opcode, payload: &Value) -> io::Result<()> {
+    let body = serde_json::to_vec(payload)?;
+    let mut frame = Vec::with_capacity(8 + body.len());
+    frame.extend_from_slice(&(opcode as u32).to_le_bytes());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&body);
+    w.write_all(&frame)?;
+    w.flush()
+}
+
+pub fn read_frame(r: &mut impl Read) -> Result<(Opcode, Value), IpcError> {
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)?;
+    let raw_op = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let opcode = Opcode::from_u32(raw_op)
+        .ok_or_else(|| IpcError::Protocol(format!("unknown opcode {}", raw_op)))?;
+    if len > MAX_FRAME_LEN {
+        return Err(IpcError::Protocol(format!("frame of {} bytes exceeds limit", len)));
+    }
+    let mut body = vec![0u8; len as usize];
+    r.read_exact(&mut body)?;
+    let payload = if body.is_empty() { Value::Null } else { serde_json::from_slice(&body)? };
+    Ok((opcode, payload))
+}
+
+fn close_error(payload: &Value) -> IpcError {
+    let code = payload["code"].as_i64().unwrap_or(0);
+    let message = payload["message"].as_str().unwrap_or("no reason given").to_string();
+    match code {
+        CLOSE_INVALID_CLIENT_ID => IpcError::InvalidClientId(message),
+        CLOSE_INVALID_VERSION => IpcError::UnsupportedVersion(message),
+        _ => IpcError::Closed { code, message },
+    }
+}
+
+/// How the Discord client owning an endpoint was installed, inferred from its path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(windows, allow(dead_code))]
+pub enum Packaging {
+    Native,
+    Flatpak,
+    Snap,
+}
+
+impl fmt::Display for Packaging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packaging::Native => write!(f, "native"),
+            Packaging::Flatpak => write!(f, "Flatpak"),
+            Packaging::Snap => write!(f, "Snap"),
+        }
+    }
+}
+
+/// Release channel of the connected Discord client, from the READY payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientFlavor {
+    Stable,
+    Ptb,
+    Canary,
+    Unknown,
+}
+
+impl ClientFlavor {
+    fn from_api_endpoint(api_endpoint: Option<&str>) -> Self {
+        match api_endpoint {
+            Some(e) if e.contains("canary.discord") => ClientFlavor::Canary,
+            Some(e) if e.contains("ptb.discord") => ClientFlavor::Ptb,
+            Some(e) if e.contains("discord.com") || e.contains("discordapp.com") => ClientFlavor::Stable,
+            _ => ClientFlavor::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ClientFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientFlavor::Stable => write!(f, "Discord"),
+            ClientFlavor::Ptb => write!(f, "Discord PTB"),
+            ClientFlavor::Canary => write!(f, "Discord Canary"),
+            ClientFlavor::Unknown => write!(f, "Discord (unknown build)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub path: String,
+    pub packaging: Packaging,
+}
+
+// Sandboxed installs expose their socket below the runtime directory
+#[cfg(not(windows))]
+const SANDBOX_DIRS: &[(&str, Packaging)] = &[
+    ("", Packaging::Native),
+    ("app/com.discordapp.Discord/", Packaging::Flatpak),
+    ("app/com.discordapp.DiscordCanary/", Packaging::Flatpak),
+    ("app/com.discordapp.DiscordPtb/", Packaging::Flatpak),
+    ("snap.discord/", Packaging::Snap),
+    ("snap.discord-canary/", Packaging::Snap),
+];
+
+/// Every path a Discord client may listen on, in the order Discord allocates them.
+pub fn endpoint_candidates() -> Vec<Endpoint> {
+    #[cfg(windows)]
+    {
+        (0..PIPE_COUNT)
+            .map(|i| Endpoint { path: format!(r"\\?\pipe\discord-ipc-{}", i), packaging: Packaging::Native })
+            .collect()
+    }
+    #[cfg(not(windows))]
+    {
+        let base = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+            .unwrap_or_else(|| "/tmp".to_string());
+        let base = base.trim_end_matches('/').to_string();
+        SANDBOX_DIRS
+            .iter()
+            .flat_map(|(dir, packaging)| {
+                let base = base.clone();
+                (0..PIPE_COUNT).map(move |i| Endpoint {
+                    path: format!("{}/{}discord-ipc-{}", base, dir, i),
+                    packaging: *packaging,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Candidates that currently exist, i.e. running Discord clients.
+pub fn discover_endpoints() -> Vec<Endpoint> {
+    endpoint_candidates()
+        .into_iter()
+        .filter(|e| std::path::Path::new(&e.path).exists())
+        .collect()
+}
+
+fn packaging_of(path: &str) -> Packaging {
+    endpoint_candidates()
+        .into_iter()
+        .find(|e| e.path == path)
+        .map_or(Packaging::Native, |e| e.packaging)
+}
+
+fn open_endpoint(path: &str) -> io::Result<Box<dyn IpcStream>> {
+    let timeout = Duration::from_millis(IPC_TIMEOUT_MS);
+    #[cfg(unix)]
+    {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Box::new(stream))
+    }
+    #[cfg(windows)]
+    {
+        Ok(Box::new(TimedPipe::open(path, timeout)?))
+    }
+}
+
+/// A named pipe opened for overlapped I/O, so every read and write gives up
+/// after `timeout` like a Unix socket with read and write timeouts.
+#[cfg(windows)]
+struct TimedPipe {
+    file: std::fs::File,
+    /// Signalled when the pending operation completes.
+    event: windows::Win32::Foundation::HANDLE,
+    timeout: Duration,
+}
+
+#[cfg(windows)]
+impl TimedPipe {
+    fn open(path: &str, timeout: Duration) -> io::Result<Self> {
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows::core::PCWSTR;
+        use windows::Win32::Storage::FileSystem::FILE_FLAG_OVERLAPPED;
+        use windows::Win32::System::Threading::CreateEventW;
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(FILE_FLAG_OVERLAPPED.0)
+            .open(path)?;
+        let event = unsafe { CreateEventW(None, true, false, PCWSTR::null()) }?;
+        Ok(Self { file, event, timeout })
+    }
+
+    /// Starts one overlapped operation with `start` and waits for it, cancelling
+    /// it once `timeout` passes. Returns the number of bytes transferred.
+    fn transfer(
+        &self,
+        start: impl FnOnce(
+            windows::Win32::Foundation::HANDLE,
+            *mut windows::Win32::System::IO::OVERLAPPED,
+        ) -> windows::core::Result<()>,
+    ) -> io::Result<usize> {
+        use std::os::windows::io::AsRawHandle;
+        use windows::Win32::Foundation::{ERROR_IO_PENDING, HANDLE, WAIT_TIMEOUT};
+        use windows::Win32::System::Threading::{ResetEvent, WaitForSingleObject};
+        use windows::Win32::System::IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED};
+
+        let handle = HANDLE(self.file.as_raw_handle() as isize);
+        let mut overlapped = OVERLAPPED { hEvent: self.event, ..Default::default() };
+        let mut transferred = 0u32;
+        unsafe {
+            ResetEvent(self.event)?;
+            match start(handle, &mut overlapped) {
+                Ok(()) => {}
+                Err(e) if e.code() == ERROR_IO_PENDING.to_hresult() => {
+                    let millis = self.timeout.as_millis().min(u32::MAX as u128) as u32;
+                    if WaitForSingleObject(self.event, millis) == WAIT_TIMEOUT {
+                        let _ = CancelIoEx(handle, Some(&overlapped));
+                        // Wait for the cancellation so the buffer is no longer in use;
+                        // the operation may still have finished first
+                        return match GetOverlappedResult(handle, &overlapped, &mut transferred, true) {
+                            Ok(()) => Ok(transferred as usize),
+                            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+                        };
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+            GetOverlappedResult(handle, &overlapped, &mut transferred, true)?;
+        }
+        Ok(transferred as usize)
+    }
+}
+
+#[cfg(windows)]
+impl Read for TimedPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use windows::Win32::Storage::FileSystem::ReadFile;
+        self.transfer(|handle, overlapped| unsafe { ReadFile(handle, Some(buf), None, Some(overlapped)) })
+    }
+}
+
+#[cfg(windows)]
+impl Write for TimedPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        use windows::Win32::Storage::FileSystem::WriteFile;
+        self.transfer(|handle, overlapped| unsafe { WriteFile(handle, Some(buf), None, Some(overlapped)) })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl Drop for TimedPipe {
+    fn drop(&mut self) {
+        let _ = unsafe { windows::Win32::Foundation::CloseHandle(self.event) };
+    }
+}
+
+/// A handshaken connection to the Discord client.
+pub struct IpcConnection {
+    stream: Box<dyn IpcStream>,
+    endpoint: Endpoint,
+    flavor: ClientFlavor,
+    user: DiscordUser,
+    next_nonce: u64,
+}
+
+impl IpcConnection {
+    /// Connects to `preferred` if given, otherwise to the first endpoint that accepts,
+    /// and performs the handshake.
+    pub fn connect(client_id: &str, preferred: Option<&str>) -> Result<Self, IpcError> {
+        let candidates = match preferred {
+            Some(path) => vec![Endpoint { path: path.to_string(), packaging: packaging_of(path) }],
+            None => endpoint_candidates(),
+        };
+
+        let mut last_err = IpcError::NotRunning;
+        for endpoint in candidates {
+            match open_endpoint(&endpoint.path) {
+                Ok(stream) => return Self::handshake(stream, endpoint, client_id),
+                Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {}
+                Err(e) => last_err = IpcError::Io(e),
+            }
+        }
+        Err(last_err)
+    }
+
+    fn handshake(mut stream: Box<dyn IpcStream>, endpoint: Endpoint, client_id: &str) -> Result<Self, IpcError> {
+        write_frame(&mut stream, Opcode::Handshake, &json!({ "v": 1, "client_id": client_id }))?;
+        loop {
+            let (opcode, payload) = read_frame(&mut stream)?;
+            match opcode {
+                Opcode::Close => return Err(close_error(&payload)),
+                Opcode::Ping => write_frame(&mut stream, Opcode::Pong, &payload)?,
+                Opcode::Frame if payload["evt"] == "READY" => {
+                    let user = serde_json::from_value(payload["data"]["user"].clone())
+                        .map_err(|e| IpcError::Protocol(format!("READY without user: {}", e)))?;
+                    let flavor = ClientFlavor::from_api_endpoint(payload["data"]["config"]["api_endpoint"].as_str());
+                    return Ok(Self { stream, endpoint, flavor, user, next_nonce: 0 });
+                }
+                Opcode::Frame if payload["evt"] == "ERROR" => {
+                    let code = payload["data"]["code"].as_i64().unwrap_or(0);
+                    let message = payload["data"]["message"].as_str().unwrap_or_default().to_string();
+                    return Err(match code {
+                        CLOSE_INVALID_CLIENT_ID => IpcError::InvalidClientId(message),
+                        CLOSE_INVALID_VERSION => IpcError::UnsupportedVersion(message),
+                        _ => IpcError::Discord { code, message },
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn user(&self) -> &DiscordUser {
+        &self.user
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    pub fn flavor(&self) -> ClientFlavor {
+        self.flavor
+    }
+
+    /// Sends a command and waits for the response carrying the same nonce.
+    pub fn command(&mut self, cmd: &str, args: Value) -> Result<Value, IpcError> {
+        self.next_nonce += 1;
+        let nonce = format!("{}-{}", std::process::id(), self.next_nonce);
+        write_frame(
+            &mut self.stream,
+            Opcode::Frame,
+            &json!({ "cmd": cmd, "args": args, "nonce": nonce }),
+        )?;
+
+        loop {
+            let (opcode, payload) = read_frame(&mut self.stream)?;
+            match opcode {
+                Opcode::Close => return Err(close_error(&payload)),
+                Opcode::Ping => write_frame(&mut self.stream, Opcode::Pong, &payload)?,
+                Opcode::Frame if payload["nonce"] == nonce.as_str() => {
+                    if payload["evt"] == "ERROR" {
+                        return Err(IpcError::Discord {
+                            code: payload["data"]["code"].as_i64().unwrap_or(0),
+                            message: payload["data"]["message"].as_str().unwrap_or_default().to_string(),
+                        });
+                    }
+                    return Ok(payload["data"].clone());
+                }
+                // Unrelated events and stale responses
+                _ => {}
+            }
+        }
+    }
+
+    /// Sets the activity, or clears it when `None`.
+    pub fn set_activity(&mut self, activity: Option<&Activity>) -> Result<Value, IpcError> {
+        let args = json!({ "pid": std::process::id(), "activity": activity });
+        self.command("SET_ACTIVITY", args)
+    }
+
+    /// Politely closes the connection; errors are irrelevant at this point.
+    pub fn close(mut self) {
+        let _ = write_frame(&mut self.stream, Opcode::Close, &json!({}));
+    }
+}
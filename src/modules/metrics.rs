@@ -0,0 +1,117 @@
+//! Prometheus metrics, served as text at `/metrics` by the local HTTP API.
+
+use crate::modules::app::AppState;
+use crate::modules::config::GAME_NAME;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Upper bounds of the scan duration histogram, in seconds
+const SCAN_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Counters that can't be derived from the rest of `AppState`.
+#[derive(Default)]
+pub struct Metrics {
+    scan_buckets: [u64; SCAN_BUCKETS.len()],
+    scan_count: u64,
+    scan_sum: f64,
+    /// Seconds of finished sessions per window title; kept beyond the session history cap.
+    session_seconds: BTreeMap<String, i64>,
+}
+
+impl Metrics {
+    pub fn observe_scan(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, bound) in self.scan_buckets.iter_mut().zip(SCAN_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.scan_count += 1;
+        self.scan_sum += secs;
+    }
+
+    pub fn add_session(&mut self, title: Option<&str>, secs: i64) {
+        *self.session_seconds.entry(title.unwrap_or(GAME_NAME).to_string()).or_default() += secs.max(0);
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub fn render(state: &AppState) -> String {
+    let metrics = &state.metrics;
+    let mut out = String::new();
+
+    gauge(&mut out, "motorstorm_game_running", "Whether the game is currently detected.", state.game_running as u8);
+    gauge(&mut out, "motorstorm_discord_connected", "Whether Discord IPC is connected.", state.discord.connected as u8);
+    gauge(&mut out, "motorstorm_presence_paused", "Whether privacy mode is on.", state.presence_paused as u8);
+
+    // Finished sessions plus the one in progress, so the counter rises while playing
+    let mut sessions = metrics.session_seconds.clone();
+    if let (true, Some(start)) = (state.game_running, state.start_timestamp) {
+        let title = state.matched_window.as_deref().unwrap_or(GAME_NAME).to_string();
+        *sessions.entry(title).or_default() += (Utc::now().timestamp() - start).max(0);
+    }
+    header(&mut out, "motorstorm_session_seconds_total", "Seconds played per window title.", "counter");
+    for (title, secs) in &sessions {
+        let _ = writeln!(out, "motorstorm_session_seconds_total{{title=\"{}\"}} {}", escape(title), secs);
+    }
+
+    header(&mut out, "motorstorm_scan_duration_seconds", "Duration of process scans.", "histogram");
+    for (count, bound) in metrics.scan_buckets.iter().zip(SCAN_BUCKETS) {
+        let _ = writeln!(out, "motorstorm_scan_duration_seconds_bucket{{le=\"{}\"}} {}", bound, count);
+    }
+    let _ = writeln!(out, "motorstorm_scan_duration_seconds_bucket{{le=\"+Inf\"}} {}", metrics.scan_count);
+    let _ = writeln!(out, "motorstorm_scan_duration_seconds_sum {}", metrics.scan_sum);
+    let _ = writeln!(out, "motorstorm_scan_duration_seconds_count {}", metrics.scan_count);
+
+    let updates = state.discord.updates;
+    header(&mut out, "motorstorm_discord_updates_total", "Activity updates sent to Discord, by result.", "counter");
+    let _ = writeln!(out, "motorstorm_discord_updates_total{{result=\"success\"}} {}", updates.sent);
+    let _ = writeln!(out, "motorstorm_discord_updates_total{{result=\"failure\"}} {}", updates.failed);
+    let _ = writeln!(out, "motorstorm_discord_updates_total{{result=\"suppressed\"}} {}", updates.suppressed);
+
+    gauge(&mut out, "motorstorm_process_cpu_percent", "CPU usage of this app.", state.cpu_usage);
+    gauge(&mut out, "motorstorm_process_resident_memory_bytes", "Memory used by this app.", state.ram_usage);
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::app::SessionRecord;
+
+    #[test]
+    fn renders_sessions_scans_and_state() {
+        let mut state = AppState::new();
+        state.record_session(SessionRecord { window: Some("Race \"1\"".to_string()), start_timestamp: 0, end_timestamp: 120 });
+        state.record_session(SessionRecord { window: Some("Race \"1\"".to_string()), start_timestamp: 200, end_timestamp: 230 });
+        state.metrics.observe_scan(Duration::from_millis(3));
+        state.metrics.observe_scan(Duration::from_millis(300));
+        state.game_running = true;
+
+        let text = render(&state);
+        assert!(text.contains("motorstorm_game_running 1\n"));
+        assert!(text.contains("motorstorm_discord_connected 0\n"));
+        assert!(text.contains("motorstorm_session_seconds_total{title=\"Race \\\"1\\\"\"} 150\n"));
+        assert!(text.contains("motorstorm_scan_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(text.contains("motorstorm_scan_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("motorstorm_scan_duration_seconds_bucket{le=\"0.5\"} 2\n"));
+        assert!(text.contains("motorstorm_scan_duration_seconds_count 2\n"));
+        assert!(text.contains("# TYPE motorstorm_discord_updates_total counter\n"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Test support: a minimal local HTTP server standing in for remote services.
+//!
+//! `MockHttp` answers every request with the next queued response (or the
+//! default one), one request per connection, and records what it received.
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_MS: u64 = 10;
+
+/// A request as received by the mock.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+struct MockState {
+    requests: Vec<Request>,
+    responses: VecDeque<(u16, String)>,
+    default: (u16, String),
+}
+
+pub struct MockHttp {
+    port: u16,
+    state: Arc<Mutex<MockState>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockHttp {
+    /// Starts a server on an ephemeral localhost port that answers `200` with `body`.
+    pub fn start(body: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock http");
+        listener.set_nonblocking(true).expect("nonblocking listener");
+        let port = listener.local_addr().expect("local addr").port();
+        let mock = Self {
+            port,
+            state: Arc::new(Mutex::new(MockState {
+                requests: Vec::new(),
+                responses: VecDeque::new(),
+                default: (200, body.to_string()),
+            })),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+
+        let state = mock.state.clone();
+        let stop = mock.stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let state = state.clone();
+                        thread::spawn(move || serve(stream, state));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(POLL_MS)),
+                    Err(_) => break,
+                }
+            }
+        });
+        mock
+    }
+
+    /// Full URL of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// Answers the next request with `status` and `body` instead of the default.
+    pub fn respond(&self, status: u16, body: &str) {
+        self.state.lock().responses.push_back((status, body.to_string()));
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().requests.clone()
+    }
+
+    /// Waits until at least `count` requests were received.
+    pub fn wait_for_requests(&self, count: usize, timeout: Duration) -> Vec<Request> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let requests = self.requests();
+            if requests.len() >= count || Instant::now() >= deadline {
+                return requests;
+            }
+            thread::sleep(Duration::from_millis(POLL_MS));
+        }
+    }
+}
+
+impl Drop for MockHttp {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn serve(stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    let _ = reader.read_exact(&mut body);
+
+    let (status, response) = {
+        let mut state = state.lock();
+        state.requests.push(Request { method, path, body: String::from_utf8_lossy(&body).into_owned() });
+        state.responses.pop_front().unwrap_or_else(|| state.default.clone())
+    };
+    let mut stream = reader.into_inner();
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+}
@@ -0,0 +1,268 @@
+//! Test support: an in-process stand-in for the Discord client's IPC socket.
+//!
+//! `MockDiscord` listens on `discord-ipc-N` inside a temporary directory,
+//! answers the handshake with a READY event, records every SET_ACTIVITY
+//! payload and can inject errors or disconnects into the next frame.
+
+use crate::modules::ipc::{read_frame, write_frame, IpcError, Opcode};
+use parking_lot::{const_mutex, Mutex, MutexGuard};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const POLL_MS: u64 = 10;
+
+/// Guards process-wide environment changes so tests using the mock can run in parallel.
+static ENV_LOCK: Mutex<()> = const_mutex(());
+
+/// A fault applied to the next frame the mock receives.
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Answer with an ERROR event (or, during the handshake, an ERROR dispatch).
+    Error { code: i64, message: String },
+    /// Answer with a CLOSE frame, e.g. code 4000 for an invalid client ID.
+    Close { code: i64, message: String },
+    /// Drop the connection without answering.
+    Disconnect,
+}
+
+struct MockState {
+    handshakes: Vec<String>,
+    /// `args.activity` of every SET_ACTIVITY received; `Null` means cleared.
+    activities: Vec<Value>,
+    faults: VecDeque<Fault>,
+    /// `config.api_endpoint` reported in READY, which identifies the client flavor.
+    api_endpoint: String,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            handshakes: Vec::new(),
+            activities: Vec::new(),
+            faults: VecDeque::new(),
+            api_endpoint: "//discord.com/api".to_string(),
+        }
+    }
+}
+
+pub struct MockDiscord {
+    dir: Arc<TempDir>,
+    socket: PathBuf,
+    state: Arc<Mutex<MockState>>,
+    stop: Arc<AtomicBool>,
+}
+
+/// Points `XDG_RUNTIME_DIR` at a mock until dropped.
+pub struct EnvGuard {
+    previous: Option<String>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        match &self.previous {
+            Some(v) => std::env::set_var("XDG_RUNTIME_DIR", v),
+            None => std::env::remove_var("XDG_RUNTIME_DIR"),
+        }
+    }
+}
+
+/// Points `XDG_RUNTIME_DIR` at `dir` while holding the environment lock.
+pub fn use_runtime_dir(dir: &Path) -> EnvGuard {
+    let lock = ENV_LOCK.lock();
+    let previous = std::env::var("XDG_RUNTIME_DIR").ok();
+    std::env::set_var("XDG_RUNTIME_DIR", dir);
+    EnvGuard { previous, _lock: lock }
+}
+
+impl MockDiscord {
+    /// Starts a mock listening on `discord-ipc-0` in a fresh temp directory.
+    pub fn start() -> Self {
+        Self::start_at(0)
+    }
+
+    pub fn start_at(index: u8) -> Self {
+        Self::start_in("", index)
+    }
+
+    /// Starts a mock on `<subdir>/discord-ipc-<index>`, e.g. a Flatpak or Snap path.
+    pub fn start_in(subdir: &str, index: u8) -> Self {
+        let dir = Arc::new(tempfile::tempdir().expect("create temp dir"));
+        Self::bind(dir, subdir, index)
+    }
+
+    /// Starts a second client in the same runtime directory, like PTB next to Stable.
+    pub fn sibling(&self, index: u8) -> Self {
+        Self::bind(self.dir.clone(), "", index)
+    }
+
+    fn bind(dir: Arc<TempDir>, subdir: &str, index: u8) -> Self {
+        let parent = dir.path().join(subdir);
+        std::fs::create_dir_all(&parent).expect("create socket dir");
+        let socket = parent.join(format!("discord-ipc-{}", index));
+        let mock = Self {
+            dir,
+            socket,
+            state: Arc::new(Mutex::new(MockState::default())),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        mock.listen();
+        mock
+    }
+
+    fn listen(&self) {
+        let listener = UnixListener::bind(&self.socket).expect("bind mock socket");
+        listener.set_nonblocking(true).expect("nonblocking listener");
+        let state = self.state.clone();
+        let stop = self.stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let state = state.clone();
+                        let stop = stop.clone();
+                        thread::spawn(move || serve(stream, state, stop));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(POLL_MS)),
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    /// The directory to use as `XDG_RUNTIME_DIR`.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Makes this mock the one `DiscordClient::connect` finds, until the guard is dropped.
+    pub fn activate(&self) -> EnvGuard {
+        use_runtime_dir(self.dir())
+    }
+
+    pub fn set_api_endpoint(&self, api_endpoint: &str) {
+        self.state.lock().api_endpoint = api_endpoint.to_string();
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().faults.push_back(fault);
+    }
+
+    /// Client IDs of every handshake received, in order.
+    pub fn handshakes(&self) -> Vec<String> {
+        self.state.lock().handshakes.clone()
+    }
+
+    pub fn activities(&self) -> Vec<Value> {
+        self.state.lock().activities.clone()
+    }
+
+    /// Waits until at least `count` activities were recorded.
+    pub fn wait_for_activities(&self, count: usize, timeout: Duration) -> Vec<Value> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let activities = self.activities();
+            if activities.len() >= count || Instant::now() >= deadline {
+                return activities;
+            }
+            thread::sleep(Duration::from_millis(POLL_MS));
+        }
+    }
+
+    /// Stops accepting and closes open connections, as if Discord quit.
+    pub fn shutdown(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+impl Drop for MockDiscord {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn serve(mut stream: UnixStream, state: Arc<Mutex<MockState>>, stop: Arc<AtomicBool>) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(POLL_MS * 5)));
+
+    while !stop.load(Ordering::Relaxed) {
+        let (opcode, payload) = match read_frame(&mut stream) {
+            Ok(frame) => frame,
+            Err(IpcError::Timeout) => continue,
+            Err(_) => return,
+        };
+
+        let fault = match opcode {
+            Opcode::Handshake | Opcode::Frame => state.lock().faults.pop_front(),
+            _ => None,
+        };
+        let reply = match (opcode, fault) {
+            (Opcode::Close, _) => return,
+            (Opcode::Ping, _) => write_frame(&mut stream, Opcode::Pong, &payload),
+            (_, Some(Fault::Disconnect)) => return,
+            (_, Some(Fault::Close { code, message })) => {
+                let _ = write_frame(&mut stream, Opcode::Close, &json!({ "code": code, "message": message }));
+                return;
+            }
+            (_, Some(Fault::Error { code, message })) => write_frame(
+                &mut stream,
+                Opcode::Frame,
+                &json!({
+                    "cmd": payload.get("cmd").cloned().unwrap_or(json!("DISPATCH")),
+                    "evt": "ERROR",
+                    "nonce": payload.get("nonce").cloned().unwrap_or(Value::Null),
+                    "data": { "code": code, "message": message },
+                }),
+            ),
+            (Opcode::Handshake, None) => {
+                let client_id = payload["client_id"].as_str().unwrap_or_default().to_string();
+                let api_endpoint = {
+                    let mut state = state.lock();
+                    state.handshakes.push(client_id);
+                    state.api_endpoint.clone()
+                };
+                write_frame(
+                    &mut stream,
+                    Opcode::Frame,
+                    &json!({
+                        "cmd": "DISPATCH",
+                        "evt": "READY",
+                        "nonce": null,
+                        "data": {
+                            "v": 1,
+                            "config": { "api_endpoint": api_endpoint, "environment": "production" },
+                            "user": { "id": "42", "username": "mockracer", "global_name": "Mock Racer" },
+                        },
+                    }),
+                )
+            }
+            (Opcode::Frame, None) => {
+                if payload["cmd"] == "SET_ACTIVITY" {
+                    state.lock().activities.push(payload["args"]["activity"].clone());
+                }
+                write_frame(
+                    &mut stream,
+                    Opcode::Frame,
+                    &json!({ "cmd": payload["cmd"], "evt": null, "nonce": payload["nonce"], "data": payload["args"]["activity"] }),
+                )
+            }
+            (Opcode::Pong, None) => Ok(()),
+        };
+        if reply.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Test support: a minimal local MQTT 3.1.1 broker.
+//!
+//! `MockMqtt` accepts any client, acknowledges QoS 0/1 publishes and keeps
+//! retained messages the way a real broker does: an empty retained payload
+//! clears the topic, and a client's Last Will is applied when its connection
+//! drops without a DISCONNECT.
+
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_MS: u64 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+/// A CONNECT as received by the mock.
+#[derive(Clone, Debug)]
+pub struct Connect {
+    pub client_id: String,
+    pub username: Option<String>,
+    pub will: Option<Message>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    connects: Vec<Connect>,
+    messages: Vec<Message>,
+    retained: BTreeMap<String, String>,
+    streams: Vec<TcpStream>,
+}
+
+impl BrokerState {
+    fn publish(&mut self, message: Message) {
+        if message.retain {
+            if message.payload.is_empty() {
+                self.retained.remove(&message.topic);
+            } else {
+                self.retained.insert(message.topic.clone(), message.payload.clone());
+            }
+        }
+        self.messages.push(message);
+    }
+}
+
+pub struct MockMqtt {
+    port: u16,
+    state: Arc<Mutex<BrokerState>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockMqtt {
+    /// Starts a broker on an ephemeral localhost port.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock mqtt");
+        listener.set_nonblocking(true).expect("nonblocking listener");
+        let port = listener.local_addr().expect("local addr").port();
+        let mock = Self { port, state: Arc::default(), stop: Arc::new(AtomicBool::new(false)) };
+
+        let state = mock.state.clone();
+        let stop = mock.stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let state = state.clone();
+                        thread::spawn(move || serve(stream, state));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(POLL_MS)),
+                    Err(_) => break,
+                }
+            }
+        });
+        mock
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn connects(&self) -> Vec<Connect> {
+        self.state.lock().connects.clone()
+    }
+
+    pub fn messages(&self) -> Vec<Message> {
+        self.state.lock().messages.clone()
+    }
+
+    /// The retained payload of `topic`, if any.
+    pub fn retained(&self, topic: &str) -> Option<String> {
+        self.state.lock().retained.get(topic).cloned()
+    }
+
+    /// Drops every client connection, as if the network failed.
+    pub fn kick(&self) {
+        for stream in self.state.lock().streams.drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Waits until `condition` holds for the broker.
+    pub fn wait_for(&self, timeout: Duration, condition: impl Fn(&MockMqtt) -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if condition(self) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(POLL_MS));
+        }
+    }
+}
+
+impl Drop for MockMqtt {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.kick();
+    }
+}
+
+fn serve(mut stream: TcpStream, state: Arc<Mutex<BrokerState>>) {
+    let _ = stream.set_nonblocking(false);
+    if let Ok(clone) = stream.try_clone() {
+        state.lock().streams.push(clone);
+    }
+
+    let mut will = None;
+    // Ends on DISCONNECT (Ok) or a dropped connection (Err)
+    let result = (|| -> std::io::Result<()> {
+        loop {
+            let (header, body) = read_packet(&mut stream)?;
+            match header >> 4 {
+                1 => {
+                    let connect = parse_connect(&body);
+                    will = connect.will.clone();
+                    state.lock().connects.push(connect);
+                    stream.write_all(&[0x20, 0x02, 0x00, 0x00])?;
+                }
+                3 => {
+                    let qos = (header >> 1) & 0b11;
+                    let mut pos = 0;
+                    let topic = read_string(&body, &mut pos);
+                    if qos > 0 {
+                        stream.write_all(&[0x40, 0x02, body[pos], body[pos + 1]])?;
+                        pos += 2;
+                    }
+                    let payload = String::from_utf8_lossy(&body[pos..]).into_owned();
+                    state.lock().publish(Message { topic, payload, retain: header & 1 == 1 });
+                }
+                12 => stream.write_all(&[0xD0, 0x00])?,
+                14 => return Ok(()),
+                _ => {}
+            }
+        }
+    })();
+
+    if result.is_err() {
+        if let Some(will) = will {
+            state.lock().publish(will);
+        }
+    }
+}
+
+fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+    let (mut length, mut shift) = (0usize, 0);
+    loop {
+        stream.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok((header, body))
+}
+
+fn read_bytes<'a>(body: &'a [u8], pos: &mut usize) -> &'a [u8] {
+    let len = u16::from_be_bytes([body[*pos], body[*pos + 1]]) as usize;
+    *pos += 2 + len;
+    &body[*pos - len..*pos]
+}
+
+fn read_string(body: &[u8], pos: &mut usize) -> String {
+    String::from_utf8_lossy(read_bytes(body, pos)).into_owned()
+}
+
+fn parse_connect(body: &[u8]) -> Connect {
+    let mut pos = 0;
+    read_string(body, &mut pos); // protocol name
+    let flags = body[pos + 1];
+    pos += 4; // level, flags, keep alive
+    let client_id = read_string(body, &mut pos);
+    let will = (flags & 0b100 != 0).then(|| Message {
+        topic: read_string(body, &mut pos),
+        payload: String::from_utf8_lossy(read_bytes(body, &mut pos)).into_owned(),
+        retain: flags & 0b10_0000 != 0,
+    });
+    let username = (flags & 0x80 != 0).then(|| read_string(body, &mut pos));
+    Connect { client_id, username, will }
+}
@@ -1,8 +1,29 @@
+pub mod api;
+pub mod assets;
+pub mod cli;
 pub mod config;
+pub mod connection;
+pub mod control;
 pub mod discord;
+pub mod discord_actor;
+pub mod events;
+pub mod hooks;
+pub mod ipc;
+pub mod metrics;
+#[cfg(test)]
+pub mod mock_http;
+#[cfg(all(test, unix))]
+pub mod mock_ipc;
+#[cfg(test)]
+pub mod mock_mqtt;
+pub mod mqtt;
+pub mod obs;
 pub mod process;
+pub mod script;
+pub mod trace;
 pub mod ui;
 pub mod app;
 pub mod utils;
+pub mod webhooks;
 pub mod window;
 pub mod admin;
@@ -0,0 +1,370 @@
+//! MQTT publishing for home automation such as Home Assistant.
+//!
+//! While `mqtt_enabled` is on, `run_mqtt` keeps these retained messages under
+//! `mqtt_topic_prefix` current:
+//!
+//! | Topic | Payload |
+//! | :--- | :--- |
+//! | `{prefix}/status` | `online`, or `offline` (also the Last Will) |
+//! | `{prefix}/state` | `playing` or `idle` |
+//! | `{prefix}/title` | Detected window title; cleared when idle |
+//! | `{prefix}/session_duration` | Seconds of the running session, `0` when idle |
+//!
+//! Unless `mqtt_discovery_prefix` is empty, matching Home Assistant discovery
+//! configs are published as well. Everything is re-sent after a reconnect.
+
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::{Settings, GAME_NAME, MQTT_KEEP_ALIVE_SECS, MQTT_RETRY_MS};
+use chrono::Utc;
+use parking_lot::Mutex;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Duration};
+
+// The session duration ticks once a second
+const MQTT_UPDATE_MS: u64 = 1000;
+const REQUEST_CAPACITY: usize = 64;
+// How long a graceful disconnect may take before the connection is just dropped
+const DISCONNECT_TIMEOUT_MS: u64 = 1000;
+
+/// Connection settings; a change means reconnecting.
+#[derive(Clone, Debug, PartialEq)]
+struct MqttConfig {
+    host: String,
+    port: u16,
+    username: String,
+    password: String,
+    topic_prefix: String,
+    discovery_prefix: String,
+}
+
+impl MqttConfig {
+    fn from_settings(settings: &Settings) -> Option<Self> {
+        settings.mqtt_enabled.then(|| Self {
+            host: settings.mqtt_host.trim().to_string(),
+            port: settings.mqtt_port,
+            username: settings.mqtt_username.clone(),
+            password: settings.mqtt_password.clone(),
+            topic_prefix: settings.mqtt_topic_prefix.clone(),
+            discovery_prefix: settings.mqtt_discovery_prefix.clone(),
+        })
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic_prefix, name)
+    }
+
+    fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Game state messages as `(topic, payload)`.
+fn state_messages(config: &MqttConfig, state: &AppState) -> Vec<(String, String)> {
+    let playing = state.game_running;
+    let title = if playing {
+        state.matched_window.clone().unwrap_or_else(|| GAME_NAME.to_string())
+    } else {
+        String::new()
+    };
+    let duration = match (playing, state.start_timestamp) {
+        (true, Some(start)) => (Utc::now().timestamp() - start).max(0),
+        _ => 0,
+    };
+    vec![
+        (config.topic("state"), if playing { "playing" } else { "idle" }.to_string()),
+        (config.topic("title"), title),
+        (config.topic("session_duration"), duration.to_string()),
+    ]
+}
+
+/// Home Assistant discovery configs as `(topic, payload)`.
+fn discovery_messages(config: &MqttConfig) -> Vec<(String, String)> {
+    if config.discovery_prefix.is_empty() {
+        return Vec::new();
+    }
+    // Lets several instances with different prefixes coexist
+    let node = config.topic_prefix.replace('/', "_");
+    let device = json!({
+        "identifiers": [node],
+        "name": "MotorStorm RPC",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let entity = |component: &str, id: &str, mut fields: serde_json::Value| {
+        fields["unique_id"] = json!(format!("{}_{}", node, id));
+        fields["availability_topic"] = json!(config.topic("status"));
+        fields["device"] = device.clone();
+        (format!("{}/{}/{}/{}/config", config.discovery_prefix, component, node, id), fields.to_string())
+    };
+    vec![
+        entity("binary_sensor", "game_running", json!({
+            "name": "Game running",
+            "state_topic": config.topic("state"),
+            "payload_on": "playing",
+            "payload_off": "idle",
+        })),
+        entity("sensor", "title", json!({
+            "name": "Game title",
+            "state_topic": config.topic("title"),
+            "icon": "mdi:gamepad-variant",
+        })),
+        entity("sensor", "session_duration", json!({
+            "name": "Session duration",
+            "state_topic": config.topic("session_duration"),
+            "unit_of_measurement": "s",
+            "device_class": "duration",
+        })),
+    ]
+}
+
+/// A broker connection and the retained messages it keeps published.
+struct Session {
+    config: MqttConfig,
+    client: AsyncClient,
+    retained: Arc<Mutex<BTreeMap<String, String>>>,
+    connected: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl Session {
+    fn start(config: MqttConfig, app_state: Arc<Mutex<AppState>>, retry: Duration) -> Self {
+        let mut options = MqttOptions::new(format!("motorstormrpc-{}", std::process::id()), &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(MQTT_KEEP_ALIVE_SECS));
+        options.set_last_will(LastWill::new(config.topic("status"), "offline", QoS::AtLeastOnce, true));
+        if !config.username.is_empty() {
+            options.set_credentials(&config.username, &config.password);
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+        let mut initial = BTreeMap::new();
+        initial.insert(config.topic("status"), "online".to_string());
+        initial.extend(discovery_messages(&config));
+        let retained = Arc::new(Mutex::new(initial));
+        let connected = Arc::new(AtomicBool::new(false));
+
+        let task = {
+            let (client, retained, connected) = (client.clone(), retained.clone(), connected.clone());
+            let address = config.address();
+            tokio::spawn(async move {
+                let mut failing = false;
+                loop {
+                    match eventloop.poll().await {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            failing = false;
+                            // Flag first so nothing published meanwhile is missed; duplicates are harmless
+                            connected.store(true, Ordering::SeqCst);
+                            app_state.lock().add_log(LogLevel::Success, format!("MQTT: connected to {}", address));
+                            for (topic, payload) in retained.lock().iter() {
+                                let _ = client.try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload.as_str());
+                            }
+                        }
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+                        Ok(_) => {}
+                        Err(e) => {
+                            // Report the first failure, not every retry
+                            if connected.swap(false, Ordering::SeqCst) || !failing {
+                                app_state
+                                    .lock()
+                                    .add_log(LogLevel::Warning, format!("MQTT: {}: {}; retrying", address, e));
+                            }
+                            failing = true;
+                            sleep(retry).await;
+                        }
+                    }
+                }
+            })
+        };
+        Self { config, client, retained, connected, task }
+    }
+
+    /// Publishes `payload` as retained on `topic` if it changed. A message the
+    /// full request queue refused isn't recorded, so the next update retries it.
+    fn publish(&self, topic: String, payload: String) {
+        let mut retained = self.retained.lock();
+        if retained.get(&topic) == Some(&payload) {
+            return;
+        }
+        // Sent on (re)connect otherwise
+        if self.connected.load(Ordering::SeqCst)
+            && self.client.try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload.as_str()).is_err()
+        {
+            return;
+        }
+        retained.insert(topic, payload);
+    }
+
+    /// Marks the app offline and disconnects cleanly, or just drops the connection.
+    async fn stop(mut self) {
+        if self.connected.load(Ordering::SeqCst) {
+            let _ = self.client.try_publish(self.config.topic("status"), QoS::AtLeastOnce, true, "offline");
+            let _ = self.client.try_disconnect();
+        }
+        // Dropping aborts the event loop if it hasn't finished by then
+        let _ = timeout(Duration::from_millis(DISCONNECT_TIMEOUT_MS), &mut self.task).await;
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Keeps the broker up to date while MQTT is enabled, following settings changes.
+pub async fn run_mqtt(app_state: Arc<Mutex<AppState>>) {
+    run_with_retry(app_state, Duration::from_millis(MQTT_RETRY_MS)).await
+}
+
+async fn run_with_retry(app_state: Arc<Mutex<AppState>>, retry: Duration) {
+    let mut session: Option<Session> = None;
+    let mut events = app_state.lock().events.subscribe();
+
+    loop {
+        let (config, messages) = {
+            let state = app_state.lock();
+            let config = MqttConfig::from_settings(&state.settings);
+            let messages = config.as_ref().map(|c| state_messages(c, &state)).unwrap_or_default();
+            (config, messages)
+        };
+        if session.as_ref().map(|s| &s.config) != config.as_ref() {
+            if let Some(old) = session.take() {
+                let address = old.config.address();
+                old.stop().await;
+                app_state.lock().add_log(LogLevel::Info, format!("MQTT: disconnected from {}", address));
+            }
+            session = config.map(|c| Session::start(c, app_state.clone(), retry));
+        }
+
+        if let Some(session) = &session {
+            for (topic, payload) in messages {
+                session.publish(topic, payload);
+            }
+        }
+
+        // Refresh right away on state changes, otherwise once a second for the duration
+        tokio::select! {
+            _ = events.recv() => {}
+            _ = sleep(Duration::from_millis(MQTT_UPDATE_MS)) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::mock_mqtt::MockMqtt;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn enable(app_state: &Arc<Mutex<AppState>>, broker: &MockMqtt) {
+        let mut state = app_state.lock();
+        state.settings.mqtt_enabled = true;
+        state.settings.mqtt_host = "127.0.0.1".to_string();
+        state.settings.mqtt_port = broker.port();
+        state.settings.mqtt_topic_prefix = "test/rpc".to_string();
+    }
+
+    async fn wait_for(broker: &MockMqtt, topic: &str, payload: Option<&str>) -> bool {
+        let deadline = tokio::time::Instant::now() + WAIT;
+        while tokio::time::Instant::now() < deadline {
+            if broker.retained(topic).as_deref() == payload {
+                return true;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publishes_retained_state_with_last_will_and_discovery() {
+        let broker = MockMqtt::start();
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        enable(&app_state, &broker);
+        let task = tokio::spawn(run_with_retry(app_state.clone(), Duration::from_millis(50)));
+
+        assert!(wait_for(&broker, "test/rpc/status", Some("online")).await);
+        assert!(wait_for(&broker, "test/rpc/state", Some("idle")).await);
+        let connect = broker.connects()[0].clone();
+        assert!(connect.client_id.starts_with("motorstormrpc-"));
+        assert_eq!(connect.username, None);
+        let will = connect.will.unwrap();
+        assert_eq!((will.topic.as_str(), will.payload.as_str(), will.retain), ("test/rpc/status", "offline", true));
+
+        let discovery: serde_json::Value = serde_json::from_str(
+            &broker.retained("homeassistant/binary_sensor/test_rpc/game_running/config").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(discovery["state_topic"], "test/rpc/state");
+        assert_eq!(discovery["availability_topic"], "test/rpc/status");
+        assert!(broker.retained("homeassistant/sensor/test_rpc/session_duration/config").is_some());
+
+        {
+            let mut state = app_state.lock();
+            state.game_running = true;
+            state.matched_window = Some("MotorStorm: Pacific Rift".to_string());
+            state.start_timestamp = Some(Utc::now().timestamp() - 90);
+        }
+        assert!(wait_for(&broker, "test/rpc/state", Some("playing")).await);
+        assert!(wait_for(&broker, "test/rpc/title", Some("MotorStorm: Pacific Rift")).await);
+        let duration: i64 = broker.retained("test/rpc/session_duration").unwrap().parse().unwrap();
+        assert!(duration >= 90);
+        assert!(broker.messages().iter().all(|m| m.retain));
+
+        // Dropping the connection without DISCONNECT triggers the Last Will
+        task.abort();
+        let _ = task.await;
+        assert!(wait_for(&broker, "test/rpc/status", Some("offline")).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn republishes_after_reconnect_and_goes_offline_when_disabled() {
+        let broker = MockMqtt::start();
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        enable(&app_state, &broker);
+        {
+            let mut state = app_state.lock();
+            state.settings.mqtt_discovery_prefix = String::new();
+            state.settings.mqtt_username = "racer".to_string();
+            state.settings.mqtt_password = "secret".to_string();
+        }
+        tokio::spawn(run_with_retry(app_state.clone(), Duration::from_millis(50)));
+        assert!(wait_for(&broker, "test/rpc/status", Some("online")).await);
+        assert!(broker.messages().iter().all(|m| !m.topic.starts_with("homeassistant/")));
+        assert_eq!(broker.connects()[0].username.as_deref(), Some("racer"));
+
+        broker.kick();
+        assert!(broker.wait_for(WAIT, |b| b.connects().len() == 2));
+        assert!(wait_for(&broker, "test/rpc/status", Some("online")).await);
+
+        app_state.lock().settings.mqtt_enabled = false;
+        assert!(wait_for(&broker, "test/rpc/status", Some("offline")).await);
+        let logs: Vec<String> = app_state.lock().logs.iter().map(|l| l.message.clone()).collect();
+        assert!(logs.iter().any(|m| m.starts_with("MQTT: disconnected from 127.0.0.1:")));
+    }
+
+    #[tokio::test]
+    async fn refused_publish_is_retried_on_the_next_update() {
+        let settings = Settings { mqtt_enabled: true, ..Default::default() };
+        let config = MqttConfig::from_settings(&settings).unwrap();
+        // Nothing polls the event loop, so the one-slot request queue stays full after one publish
+        let (client, _eventloop) = AsyncClient::new(MqttOptions::new("test", "127.0.0.1", 1), 1);
+        let session = Session {
+            config,
+            client,
+            retained: Arc::new(Mutex::new(BTreeMap::new())),
+            connected: Arc::new(AtomicBool::new(true)),
+            task: tokio::spawn(async {}),
+        };
+
+        session.publish("a".to_string(), "1".to_string());
+        session.publish("b".to_string(), "2".to_string());
+
+        let retained = session.retained.lock().clone();
+        assert_eq!(retained.get("a").map(String::as_str), Some("1"));
+        assert_eq!(retained.get("b"), None);
+    }
+}
@@ -0,0 +1,167 @@
+//! Text and JSON files for OBS "Text (from file)" sources.
+//!
+//! While enabled, `run_obs_output` keeps `game.txt`, `elapsed.txt`,
+//! `status.txt` and `presence.json` in the configured directory up to date.
+//! Files are replaced atomically so OBS never reads a half-written value, and
+//! the text files are emptied when no game is running.
+
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::GAME_NAME;
+use crate::modules::utils::format_duration;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+// Once a second keeps the elapsed timer ticking
+const OBS_UPDATE_MS: u64 = 1000;
+
+/// Contents of every output file, by file name.
+fn render(state: &AppState) -> Vec<(&'static str, String)> {
+    let now = Utc::now().timestamp();
+    let elapsed = state.start_timestamp.map(|start| now - start);
+    let (game, elapsed_text, status) = if state.game_running {
+        let status = if state.presence_paused { "Playing (presence paused)" } else { "Playing" };
+        (
+            state.matched_window.clone().unwrap_or_else(|| GAME_NAME.to_string()),
+            elapsed.map(format_duration).unwrap_or_default(),
+            status.to_string(),
+        )
+    } else {
+        (String::new(), String::new(), String::new())
+    };
+
+    let combined = json!({
+        "game_running": state.game_running,
+        "game": (!game.is_empty()).then_some(&game),
+        "session_start": state.start_timestamp,
+        "elapsed_secs": elapsed,
+        "elapsed": (!elapsed_text.is_empty()).then_some(&elapsed_text),
+        "status": (!status.is_empty()).then_some(&status),
+        "presence_paused": state.presence_paused,
+    });
+    vec![
+        ("game.txt", game),
+        ("elapsed.txt", elapsed_text),
+        ("status.txt", status),
+        ("presence.json", format!("{:#}", combined)),
+    ]
+}
+
+/// Writes `contents` to a temporary file next to `path`, then renames it over `path`.
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Writes the files whose contents differ from `written`, remembering what was written.
+fn write_changed(dir: &Path, files: Vec<(&'static str, String)>, written: &mut Vec<(&'static str, String)>) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for (name, contents) in files {
+        if written.iter().any(|(n, c)| *n == name && *c == contents) {
+            continue;
+        }
+        write_atomic(&dir.join(name), &contents)?;
+        written.retain(|(n, _)| *n != name);
+        written.push((name, contents));
+    }
+    Ok(())
+}
+
+/// Keeps the output files current while `obs_output_dir` is set.
+pub async fn run_obs_output(app_state: Arc<Mutex<AppState>>) {
+    let mut dir: Option<PathBuf> = None;
+    let mut written = Vec::new();
+    let mut failing = false;
+    let mut events = app_state.lock().events.subscribe();
+
+    loop {
+        let (configured, files) = {
+            let state = app_state.lock();
+            let configured = Some(state.settings.obs_output_dir.trim())
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from);
+            (configured, render(&state))
+        };
+        if configured != dir {
+            dir = configured;
+            written.clear();
+            failing = false;
+        }
+
+        if let Some(dir) = &dir {
+            match write_changed(dir, files, &mut written) {
+                Ok(()) => failing = false,
+                // Report once, not every second
+                Err(e) if !failing => {
+                    failing = true;
+                    app_state.lock().add_log(LogLevel::Error, format!("OBS output: {:#}", e));
+                }
+                Err(_) => {}
+            }
+        }
+
+        // Refresh right away on state changes, otherwise once a second for the timer
+        tokio::select! {
+            _ = events.recv() => {}
+            _ = sleep(Duration::from_millis(OBS_UPDATE_MS)) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn files_follow_the_session_and_empty_when_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut written = Vec::new();
+        let mut state = AppState::new();
+        state.game_running = true;
+        state.matched_window = Some("MotorStorm: Pacific Rift".to_string());
+        state.start_timestamp = Some(Utc::now().timestamp() - 65);
+
+        write_changed(dir.path(), render(&state), &mut written).unwrap();
+        assert_eq!(read(dir.path(), "game.txt"), "MotorStorm: Pacific Rift");
+        assert!(read(dir.path(), "elapsed.txt").starts_with("00:01:0"));
+        assert_eq!(read(dir.path(), "status.txt"), "Playing");
+        let combined: serde_json::Value = serde_json::from_str(&read(dir.path(), "presence.json")).unwrap();
+        assert_eq!(combined["game_running"], true);
+        assert!(combined["elapsed_secs"].as_i64().unwrap() >= 65);
+
+        state.game_running = false;
+        state.matched_window = None;
+        state.start_timestamp = None;
+        write_changed(dir.path(), render(&state), &mut written).unwrap();
+        for name in ["game.txt", "elapsed.txt", "status.txt"] {
+            assert_eq!(read(dir.path(), name), "");
+        }
+        let combined: serde_json::Value = serde_json::from_str(&read(dir.path(), "presence.json")).unwrap();
+        assert_eq!(combined["game_running"], false);
+        assert!(combined["game"].is_null());
+    }
+
+    #[test]
+    fn unchanged_files_are_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut written = Vec::new();
+        let state = AppState::new();
+
+        write_changed(dir.path(), render(&state), &mut written).unwrap();
+        fs::write(dir.path().join("game.txt"), "edited").unwrap();
+        write_changed(dir.path(), render(&state), &mut written).unwrap();
+
+        assert_eq!(read(dir.path(), "game.txt"), "edited");
+        assert!(!dir.path().join("game.tmp").exists());
+    }
+}
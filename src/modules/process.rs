@@ -1,16 +1,76 @@
 use crate::modules::config::{GAME_WINDOW_TITLES, PROCESS_NAMES};
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 use sysinfo::{ProcessRefreshKind, RefreshKind, System, UpdateKind};
 
 #[cfg(windows)]
-use windows::Win32::Foundation::{BOOL, HWND, LPARAM};
+use windows::Win32::Foundation::{CloseHandle, GetLastError, BOOL, ERROR_ACCESS_DENIED, HWND, LPARAM};
+#[cfg(windows)]
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION};
 #[cfg(windows)]
 use windows::Win32::UI::WindowsAndMessaging::{
     EnumWindows, GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible,
 };
 
+/// A single window title seen for a candidate emulator process and the keyword it matched, if any.
+#[derive(Clone, Debug)]
+pub struct TitleCheck {
+    pub title: String,
+    pub matched_keyword: Option<String>,
+}
+
+/// An emulator process found during a scan.
+#[derive(Clone, Debug)]
+pub struct ScanCandidate {
+    pub pid: u32,
+    pub name: String,
+    /// `None` when window titles can't be read on this platform or were denied.
+    pub titles: Option<Vec<TitleCheck>>,
+    /// Reading the titles was refused, as for an emulator running elevated.
+    pub access_denied: bool,
+}
+
+/// Diagnostics collected for the most recent scan while debug mode is on.
+#[derive(Clone, Debug, Default)]
+pub struct ScanReport {
+    pub candidates: Vec<ScanCandidate>,
+    pub duration: Duration,
+}
+
+/// An emulator process as seen by a scan; what detection traces record.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SeenProcess {
+    pub pid: u32,
+    pub name: String,
+    /// `None` when window titles can't be read on this platform or were denied.
+    #[serde(default)]
+    pub titles: Option<Vec<String>>,
+    /// Reading the titles was refused, as for an emulator running elevated.
+    /// An emulator without titled windows is not denied; its titles are just empty.
+    #[serde(default)]
+    pub access_denied: bool,
+}
+
+/// What a scan concluded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Detection {
+    pub found_rpcs3: bool,
+    pub found_game: bool,
+    pub matched_title: Option<String>,
+    /// Emulator process the matched window belongs to.
+    pub matched_pid: Option<u32>,
+    /// An emulator that refused access to its window titles, when the game
+    /// wasn't found; a sign it runs elevated while this process doesn't.
+    pub unreadable_pid: Option<u32>,
+}
+
 pub struct ProcessScanner {
     sys: System,
+    keywords: Vec<String>,
+    last_report: ScanReport,
+    last_duration: Duration,
+    last_seen: Vec<SeenProcess>,
 }
 
 impl ProcessScanner {
@@ -23,44 +83,108 @@ impl ProcessScanner {
                         .with_cpu()
                         .with_memory()),
             ),
+            keywords: GAME_WINDOW_TITLES.iter().map(|s| s.to_string()).collect(),
+            last_report: ScanReport::default(),
+            last_duration: Duration::ZERO,
+            last_seen: Vec::new(),
         }
     }
 
-    pub fn scan(&mut self, _debug_mode: bool) -> Result<(bool, bool, Option<String>)> {
-        self.sys.refresh_processes();
+    /// Replaces the window title keywords used to recognise the game.
+    pub fn set_keywords(&mut self, keywords: &[String]) {
+        self.keywords = keywords.iter().map(|k| k.to_lowercase()).collect();
+    }
 
-        let mut found_rpcs3 = false;
-        let mut found_game = false;
-        let mut matched_title = None;
+    pub fn scan(&mut self, debug_mode: bool) -> Result<Detection> {
+        let started = Instant::now();
+        self.sys.refresh_processes();
 
+        let mut seen = Vec::new();
         for (pid, process) in self.sys.processes() {
             let name = process.name().to_lowercase();
-            if PROCESS_NAMES.contains(&name.as_str()) {
-                found_rpcs3 = true;
-
-                // Check window title on Windows
-                #[cfg(windows)]
-                {
-                    if let Some(title) = get_window_title_for_pid(pid.as_u32()) {
-                        let t_lower = title.to_lowercase();
-                        for kw in GAME_WINDOW_TITLES {
-                            if t_lower.contains(kw) {
-                                found_game = true;
-                                matched_title = Some(title);
-                                break;
-                            }
-                        }
-                    }
+            if !PROCESS_NAMES.contains(&name.as_str()) {
+                continue;
+            }
+            // Window titles are only readable on Windows
+            let titles = get_window_titles_for_pid(pid.as_u32());
+            seen.push(SeenProcess {
+                pid: pid.as_u32(),
+                name: process.name().to_string(),
+                access_denied: titles.is_err(),
+                titles: titles.unwrap_or(None),
+            });
+        }
+        seen.sort_by_key(|p| p.pid);
+
+        self.last_duration = started.elapsed();
+        let detection = self.evaluate(&seen, debug_mode);
+        self.last_seen = seen;
+        Ok(detection)
+    }
+
+    /// Matches the window titles of `seen` against the keywords. Split from
+    /// `scan` so recorded traces go through the same matching.
+    pub fn evaluate(&mut self, seen: &[SeenProcess], debug_mode: bool) -> Detection {
+        let mut detection = Detection { found_rpcs3: !seen.is_empty(), ..Detection::default() };
+        let mut candidates = Vec::new();
+
+        for process in seen {
+            if process.access_denied && detection.unreadable_pid.is_none() {
+                detection.unreadable_pid = Some(process.pid);
+            }
+
+            let mut checks = Vec::new();
+            for title in process.titles.iter().flatten() {
+                let keyword = self.match_keyword(title);
+                if keyword.is_some() && !detection.found_game {
+                    detection.found_game = true;
+                    detection.matched_title = Some(title.clone());
+                    detection.matched_pid = Some(process.pid);
+                }
+                if debug_mode {
+                    checks.push(TitleCheck { title: title.clone(), matched_keyword: keyword });
                 }
             }
 
-            if found_game {
-                break;
+            if debug_mode {
+                candidates.push(ScanCandidate {
+                    pid: process.pid,
+                    name: process.name.clone(),
+                    titles: process.titles.as_ref().map(|_| checks),
+                    access_denied: process.access_denied,
+                });
             }
         }
 
-        Ok((found_rpcs3, found_game, matched_title))
+        if detection.found_game {
+            detection.unreadable_pid = None;
+        }
+        if debug_mode {
+            self.last_report = ScanReport { candidates, duration: self.last_duration };
+        }
+        detection
+    }
+
+    /// Diagnostics from the last scan run with debug mode enabled.
+    pub fn last_report(&self) -> &ScanReport {
+        &self.last_report
     }
+
+    /// How long the last scan took, debug mode or not.
+    pub fn last_duration(&self) -> Duration {
+        self.last_duration
+    }
+
+    /// The emulator processes found by the last scan.
+    pub fn last_seen(&self) -> &[SeenProcess] {
+        &self.last_seen
+    }
+
+    fn match_keyword(&self, title: &str) -> Option<String> {
+        let t_lower = title.to_lowercase();
+        self.keywords.iter().find(|kw| t_lower.contains(kw.as_str())).cloned()
+    }
+
     pub fn get_own_usage(&self) -> (f32, u64) {
         let pid = sysinfo::Pid::from_u32(std::process::id());
         if let Some(process) = self.sys.process(pid) {
@@ -70,12 +194,33 @@ impl ProcessScanner {
     }
 }
 
-// Windows Specific Helper
+
+/// The PS3 title ID in an RPCS3 window title, e.g. `BCES00129` from
+/// `MotorStorm: Pacific Rift [BCES00129]`.
+pub fn title_id(window_title: &str) -> Option<String> {
+    window_title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|word| {
+            word.len() == 9
+                && word[..4].chars().all(|c| c.is_ascii_uppercase())
+                && word[4..].chars().all(|c| c.is_ascii_digit())
+        })
+        .map(str::to_string)
+}
+
+#[cfg(not(windows))]
+fn get_window_titles_for_pid(_pid: u32) -> std::io::Result<Option<Vec<String>>> {
+    Ok(None)
+}
+
+// Windows Specific Helper. Fails with `PermissionDenied` only when access was
+// refused; an emulator without titled windows yields an empty list.
 #[cfg(windows)]
-fn get_window_title_for_pid(pid: u32) -> Option<String> {
+fn get_window_titles_for_pid(pid: u32) -> std::io::Result<Option<Vec<String>>> {
     struct EnumData {
         pid: u32,
-        title: Option<String>,
+        titles: Vec<String>,
+        denied: bool,
     }
 
     unsafe extern "system" fn enum_window_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
@@ -83,29 +228,91 @@ fn get_window_title_for_pid(pid: u32) -> Option<String> {
         let mut window_pid = 0;
         GetWindowThreadProcessId(hwnd, Some(&mut window_pid));
 
-        if window_pid == data.pid {
-            if IsWindowVisible(hwnd).as_bool() {
-                let len = GetWindowTextLengthW(hwnd);
-                if len > 0 {
-                    let mut buf = vec![0u16; (len + 1) as usize];
-                    let copied = GetWindowTextW(hwnd, &mut buf);
-                    if copied > 0 {
-                        buf.truncate(copied as usize);
-                        data.title = Some(String::from_utf16_lossy(&buf));
-                        return BOOL(0);
-                    }
+        if window_pid == data.pid && IsWindowVisible(hwnd).as_bool() {
+            let len = GetWindowTextLengthW(hwnd);
+            if len > 0 {
+                let mut buf = vec![0u16; (len + 1) as usize];
+                let copied = GetWindowTextW(hwnd, &mut buf);
+                if copied > 0 {
+                    buf.truncate(copied as usize);
+                    data.titles.push(String::from_utf16_lossy(&buf));
+                } else if GetLastError().is_err_and(|e| e.code() == ERROR_ACCESS_DENIED.to_hresult()) {
+                    data.denied = true;
                 }
             }
         }
         BOOL(1)
     }
 
-    let mut data = EnumData { pid, title: None };
+    let mut data = EnumData { pid, titles: Vec::new(), denied: false };
     unsafe {
         let _ = EnumWindows(
             Some(enum_window_callback),
             LPARAM(&mut data as *mut _ as isize),
         );
+        // Tell an elevated emulator, which we may not query, from one without windows yet
+        if !data.denied && data.titles.is_empty() {
+            match OpenProcess(PROCESS_QUERY_INFORMATION, false, pid) {
+                Ok(handle) => {
+                    let _ = CloseHandle(handle);
+                }
+                Err(e) => data.denied = e.code() == ERROR_ACCESS_DENIED.to_hresult(),
+            }
+        }
+    }
+    if data.denied {
+        return Err(std::io::ErrorKind::PermissionDenied.into());
+    }
+    Ok(Some(data.titles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_id_is_read_from_the_window_title() {
+        assert_eq!(title_id("FPS: 30.00 | MotorStorm: Pacific Rift [BCES00129]").as_deref(), Some("BCES00129"));
+        assert_eq!(title_id("BCUS98155 - MotorStorm").as_deref(), Some("BCUS98155"));
+        assert_eq!(title_id("MotorStorm: Pacific Rift"), None);
+        assert_eq!(title_id("XBCES00129"), None);
+    }
+
+    #[test]
+    fn first_matching_window_wins() {
+        let mut scanner = ProcessScanner::new();
+        let seen = vec![
+            SeenProcess { pid: 10, name: "rpcs3.exe".to_string(), titles: None, access_denied: true },
+            SeenProcess {
+                pid: 20,
+                name: "rpcs3.exe".to_string(),
+                titles: Some(vec!["RPCS3 0.0.30".to_string(), "MotorStorm: Pacific Rift [BCES00129]".to_string()]),
+                access_denied: false,
+            },
+        ];
+        let detection = scanner.evaluate(&seen, false);
+        assert!(detection.found_rpcs3 && detection.found_game);
+        assert_eq!(detection.matched_title.as_deref(), Some("MotorStorm: Pacific Rift [BCES00129]"));
+        assert_eq!(detection.matched_pid, Some(20));
+        assert_eq!(detection.unreadable_pid, None);
+
+        let detection = scanner.evaluate(&seen[..1], false);
+        assert!(detection.found_rpcs3 && !detection.found_game);
+        assert_eq!(detection.unreadable_pid, Some(10));
+        assert_eq!(scanner.evaluate(&[], false), Detection::default());
+    }
+
+    #[test]
+    fn emulator_without_titled_windows_is_not_unreadable() {
+        let mut scanner = ProcessScanner::new();
+        // Still starting, minimised to the tray, or running with --no-gui
+        let seen = vec![SeenProcess { pid: 10, name: "rpcs3".to_string(), titles: Some(vec![]), access_denied: false }];
+        let detection = scanner.evaluate(&seen, false);
+        assert!(detection.found_rpcs3 && !detection.found_game);
+        assert_eq!(detection.unreadable_pid, None);
+
+        // Traces recorded before `access_denied` existed load as not denied
+        let old: SeenProcess = serde_json::from_str(r#"{"pid": 10, "name": "rpcs3", "titles": []}"#).unwrap();
+        assert_eq!(old, seen[0]);
     }
-    data.title
 }
@@ -0,0 +1,272 @@
+//! Presence scripts: a user Rhai script that decides the activity while a game runs.
+//!
+//! The script defines `fn presence(ctx)` and returns a map such as
+//! `#{ details: "...", state: "...", timer: true }`, a string for the details
+//! only, or `()` for the template-based activity. `ctx` describes the session:
+//!
+//! | Field | Description |
+//! | :--- | :--- |
+//! | `game`, `window`, `title_id` | Game name, detected window title and PS3 title ID |
+//! | `start`, `elapsed_secs` | Session start (Unix time) and its length so far |
+//! | `now`, `hour`, `minute`, `weekday` | Local time; `weekday` is 1 (Monday) to 7 |
+//! | `sessions` | Number of recorded sessions |
+//! | `user` | Discord display name |
+//! | `details`, `state` | The activity the templates would show |
+//!
+//! Scripts can't touch files, modules or the network, and are stopped after
+//! `SCRIPT_TIME_LIMIT_MS`. Any failure falls back to the template activity.
+
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::{check_activity_text, GAME_NAME, SCRIPT_MAX_OPERATIONS, SCRIPT_TIME_LIMIT_MS};
+use crate::modules::discord::Presence;
+use crate::modules::process::title_id;
+use chrono::{Datelike, Local, Timelike, Utc};
+use parking_lot::Mutex;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The values a script sees as `ctx`.
+pub fn context(state: &AppState, default: &Presence) -> Map {
+    let now = Local::now();
+    let window = state.matched_window.clone().unwrap_or_default();
+    let start = state.start_timestamp.unwrap_or_else(|| Utc::now().timestamp());
+    let mut ctx = Map::new();
+    ctx.insert("game".into(), GAME_NAME.into());
+    ctx.insert("title_id".into(), title_id(&window).unwrap_or_default().into());
+    ctx.insert("window".into(), window.into());
+    ctx.insert("start".into(), start.into());
+    ctx.insert("elapsed_secs".into(), (now.timestamp() - start).max(0).into());
+    ctx.insert("now".into(), now.timestamp().into());
+    ctx.insert("hour".into(), (now.hour() as i64).into());
+    ctx.insert("minute".into(), (now.minute() as i64).into());
+    ctx.insert("weekday".into(), (now.weekday().number_from_monday() as i64).into());
+    ctx.insert("sessions".into(), (state.sessions.len() as i64).into());
+    let user = state.discord.user.as_ref().map(|u| u.display_name().to_string()).unwrap_or_default();
+    ctx.insert("user".into(), user.into());
+    ctx.insert("details".into(), default.details.clone().into());
+    ctx.insert("state".into(), default.state.clone().map_or(Dynamic::UNIT, Dynamic::from));
+    ctx
+}
+
+/// Compiles and runs the configured script, reloading it when the file changes.
+pub struct PresenceScript {
+    engine: Engine,
+    /// Path, contents and compile result of the loaded script.
+    source: Option<(String, String, Result<AST, String>)>,
+    deadline: Arc<Mutex<Option<Instant>>>,
+    output: Arc<Mutex<Vec<String>>>,
+    /// Last reported error, so a failing script isn't reported on every poll.
+    last_error: Option<String>,
+}
+
+impl PresenceScript {
+    pub fn new() -> Self {
+        let deadline = Arc::new(Mutex::new(None::<Instant>));
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(1024);
+        engine.set_max_map_size(256);
+        {
+            let deadline = deadline.clone();
+            engine.on_progress(move |_| match *deadline.lock() {
+                Some(deadline) if Instant::now() >= deadline => Some("time limit".into()),
+                _ => None,
+            });
+        }
+        // The TUI owns the terminal, so print output goes to the log
+        {
+            let output = output.clone();
+            engine.on_print(move |text| output.lock().push(text.to_string()));
+        }
+        {
+            let output = output.clone();
+            engine.on_debug(move |text, _, _| output.lock().push(text.to_string()));
+        }
+
+        Self { engine, source: None, deadline, output, last_error: None }
+    }
+
+    /// The activity to show: the script's result, or `default` when no script is set
+    /// or it fails. Also returns log entries for script output and errors.
+    pub fn presence(&mut self, path: &str, ctx: Map, default: Presence) -> (Presence, Vec<(LogLevel, String)>) {
+        let mut logs = Vec::new();
+        let path = path.trim();
+        if path.is_empty() {
+            self.source = None;
+            self.last_error = None;
+            return (default, logs);
+        }
+
+        let result = self.load(path, &mut logs).and_then(|()| self.run(ctx, &default));
+        logs.extend(self.output.lock().drain(..).map(|line| (LogLevel::Info, format!("[script] {}", line))));
+        match result {
+            Ok(presence) => {
+                if self.last_error.take().is_some() {
+                    logs.push((LogLevel::Success, "Presence script: working again".to_string()));
+                }
+                (presence, logs)
+            }
+            Err(e) => {
+                if self.last_error.as_ref() != Some(&e) {
+                    logs.push((LogLevel::Error, format!("Presence script: {} (using the default activity)", e)));
+                    self.last_error = Some(e);
+                }
+                (default, logs)
+            }
+        }
+    }
+
+    /// Compiles the script if it is new or its file changed.
+    fn load(&mut self, path: &str, logs: &mut Vec<(LogLevel, String)>) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        if self.source.as_ref().is_none_or(|(p, t, _)| p != path || *t != text) {
+            let compiled = self.engine.compile(&text).map_err(|e| format!("{}: {}", path, e));
+            if compiled.is_ok() {
+                logs.push((LogLevel::Info, format!("Presence script loaded: {}", path)));
+            }
+            self.source = Some((path.to_string(), text, compiled));
+        }
+        Ok(())
+    }
+
+    fn run(&self, ctx: Map, default: &Presence) -> Result<Presence, String> {
+        let ast = match &self.source {
+            Some((_, _, compiled)) => compiled.as_ref().map_err(Clone::clone)?,
+            None => return Err("not loaded".to_string()),
+        };
+        *self.deadline.lock() = Some(Instant::now() + Duration::from_millis(SCRIPT_TIME_LIMIT_MS));
+        let result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, "presence", (ctx,));
+        *self.deadline.lock() = None;
+        let value = result.map_err(|e| match *e {
+            rhai::EvalAltResult::ErrorTerminated(..) => format!("stopped after {} ms", SCRIPT_TIME_LIMIT_MS),
+            e => e.to_string(),
+        })?;
+        to_presence(value, default)
+    }
+}
+
+/// Converts what `presence(ctx)` returned into an activity.
+fn to_presence(value: Dynamic, default: &Presence) -> Result<Presence, String> {
+    let mut presence = default.clone();
+    let type_name = value.type_name();
+    if value.is_unit() {
+        return Ok(presence);
+    }
+    if value.is_string() {
+        presence.details = value.into_string().unwrap_or_default();
+    } else if let Some(map) = value.try_cast::<Map>() {
+        for (key, value) in map {
+            match key.as_str() {
+                "details" => presence.details = value.into_string().map_err(|t| format!("details is a {}, not a string", t))?,
+                "state" if value.is_unit() => presence.state = None,
+                "state" => presence.state = Some(value.into_string().map_err(|t| format!("state is a {}, not a string", t))?),
+                "timer" => {
+                    let timer = value.as_bool().map_err(|t| format!("timer is a {}, not a bool", t))?;
+                    presence.start_time = if timer { default.start_time } else { None };
+                }
+                other => return Err(format!("unknown activity field '{}'", other)),
+            }
+        }
+    } else {
+        return Err(format!("presence() returned a {}; expected a map, a string or ()", type_name));
+    }
+    check_activity_text("details", &presence.details)?;
+    if let Some(state) = &presence.state {
+        check_activity_text("state", state)?;
+    }
+    Ok(presence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_presence() -> Presence {
+        Presence { details: "Playing MotorStorm".to_string(), state: Some("On RPCS3".to_string()), start_time: Some(100) }
+    }
+
+    fn ctx(elapsed_secs: i64) -> Map {
+        let mut state = AppState::new();
+        state.game_running = true;
+        state.matched_window = Some("MotorStorm: Pacific Rift [BCES00129]".to_string());
+        state.start_timestamp = Some(Utc::now().timestamp() - elapsed_secs);
+        context(&state, &default_presence())
+    }
+
+    fn write(dir: &tempfile::TempDir, script: &str) -> String {
+        let path = dir.path().join("presence.rhai");
+        fs::write(&path, script).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn script_result_replaces_the_activity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            &dir,
+            r#"
+            fn presence(ctx) {
+                print("elapsed " + ctx.elapsed_secs);
+                let tips = ["Boost on the straights", "Mind the mud"];
+                #{ details: ctx.title_id + ": " + tips[ctx.elapsed_secs / 60 % 2], timer: false }
+            }
+            "#,
+        );
+        let mut script = PresenceScript::new();
+
+        let (presence, logs) = script.presence(&path, ctx(65), default_presence());
+        assert_eq!(presence.details, "BCES00129: Mind the mud");
+        assert_eq!(presence.state.as_deref(), Some("On RPCS3"));
+        assert_eq!(presence.start_time, None);
+        assert!(logs.iter().any(|(_, m)| m.starts_with("[script] elapsed 6")));
+
+        assert_eq!(script.presence("", ctx(65), default_presence()).0, default_presence());
+    }
+
+    #[test]
+    fn failures_fall_back_and_are_reported_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut script = PresenceScript::new();
+
+        let path = write(&dir, "fn presence(ctx) { #{ details: \"x\" } }");
+        let (presence, logs) = script.presence(&path, ctx(0), default_presence());
+        assert_eq!(presence, default_presence());
+        assert!(logs.iter().any(|(l, m)| matches!(l, LogLevel::Error) && m.contains("details must be 2-128 characters")));
+        assert!(script.presence(&path, ctx(0), default_presence()).1.is_empty());
+
+        write(&dir, "fn presence(ctx) { loop { } }");
+        let (presence, logs) = script.presence(&path, ctx(0), default_presence());
+        assert_eq!(presence, default_presence());
+        assert!(logs.iter().any(|(_, m)| m.contains("stopped after")));
+
+        write(&dir, "fn presence(ctx) { \"Back on track\" }");
+        let (presence, logs) = script.presence(&path, ctx(0), default_presence());
+        assert_eq!(presence.details, "Back on track");
+        assert!(logs.iter().any(|(_, m)| m == "Presence script: working again"));
+    }
+
+    #[test]
+    fn scripts_are_sandboxed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut script = PresenceScript::new();
+
+        let path = write(&dir, "import \"other\" as other; fn presence(ctx) { () }");
+        let (presence, logs) = script.presence(&path, ctx(0), default_presence());
+        assert_eq!(presence, default_presence());
+        assert!(logs.iter().any(|(l, _)| matches!(l, LogLevel::Error)));
+
+        let path = write(&dir, "fn presence(ctx) { eval(\"1\") }");
+        let (_, logs) = script.presence(&path, ctx(0), default_presence());
+        assert!(logs.iter().any(|(l, _)| matches!(l, LogLevel::Error)));
+    }
+}
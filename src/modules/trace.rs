@@ -0,0 +1,329 @@
+//! Detection traces: record every scan to a file and replay it later.
+//!
+//! `--record <file>` appends one JSON line per scan (the emulator processes
+//! seen, their window titles, when the scan ran and how long it took), plus a
+//! `settings` line whenever the settings detection depends on change:
+//!
+//! ```text
+//! {"type":"settings","keywords":["motorstorm"],"start_cooldown_ms":5000,...}
+//! {"type":"scan","at_ms":1760000000000,"scan_us":850,"processes":[{"pid":4242,"name":"rpcs3.exe","titles":["MotorStorm: Pacific Rift [BCES00129]"]}]}
+//! ```
+//!
+//! `--replay <file>` feeds the scans through the same keyword matching, state
+//! machine and presence pipeline as a live run, using the recorded times, and
+//! prints what happened. Nothing is sent to Discord; tests drive a mock instead.
+
+use crate::modules::app::AppState;
+use crate::modules::config::Settings;
+use crate::modules::discord::Presence;
+use crate::modules::process::{ProcessScanner, SeenProcess};
+use crate::modules::utils::{desired_presence, Detector};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+/// The settings that decide what a scan means.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceSettings {
+    pub keywords: Vec<String>,
+    pub start_cooldown_ms: i64,
+    pub stop_cooldown_ms: i64,
+    pub details_template: String,
+    pub state_template: String,
+}
+
+impl TraceSettings {
+    fn from_settings(settings: &Settings) -> Self {
+        Self {
+            keywords: settings.keywords.clone(),
+            start_cooldown_ms: settings.start_cooldown_ms,
+            stop_cooldown_ms: settings.stop_cooldown_ms,
+            details_template: settings.details_template.clone(),
+            state_template: settings.state_template.clone(),
+        }
+    }
+
+    fn apply(&self, settings: &mut Settings) {
+        settings.keywords = self.keywords.clone();
+        settings.start_cooldown_ms = self.start_cooldown_ms;
+        settings.stop_cooldown_ms = self.stop_cooldown_ms;
+        settings.details_template = self.details_template.clone();
+        settings.state_template = self.state_template.clone();
+    }
+}
+
+/// One line of a trace file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceLine {
+    Settings(TraceSettings),
+    Scan {
+        /// When the scan ran, Unix time in milliseconds.
+        at_ms: i64,
+        /// How long it took.
+        scan_us: u64,
+        processes: Vec<SeenProcess>,
+    },
+}
+
+/// Appends scans to a trace file, flushing each line so a crash keeps the trace.
+pub struct TraceRecorder {
+    pub path: String,
+    writer: BufWriter<File>,
+    last_settings: Option<TraceSettings>,
+}
+
+impl TraceRecorder {
+    /// Creates (or truncates) `path`.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create trace {}", path))?;
+        Ok(Self { path: path.to_string(), writer: BufWriter::new(file), last_settings: None })
+    }
+
+    /// Writes a `settings` line if the detection settings differ from the last one written.
+    pub fn record_settings(&mut self, settings: &Settings) -> Result<()> {
+        let current = TraceSettings::from_settings(settings);
+        if self.last_settings.as_ref() == Some(&current) {
+            return Ok(());
+        }
+        self.write(&TraceLine::Settings(current.clone()))?;
+        self.last_settings = Some(current);
+        Ok(())
+    }
+
+    pub fn record_scan(&mut self, at_ms: i64, duration: Duration, processes: &[SeenProcess]) -> Result<()> {
+        self.write(&TraceLine::Scan { at_ms, scan_us: duration.as_micros() as u64, processes: processes.to_vec() })
+    }
+
+    fn write(&mut self, line: &TraceLine) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, line)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush().with_context(|| format!("Failed to write trace {}", self.path))
+    }
+}
+
+/// Reads a trace file.
+pub fn load(path: &str) -> Result<Vec<TraceLine>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read trace {}", path))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).with_context(|| format!("{}:{}: invalid trace line", path, i + 1)))
+        .collect()
+}
+
+/// Something that happened during a replay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayLine {
+    /// Time of the scan that caused it, Unix time in milliseconds.
+    pub at_ms: i64,
+    pub text: String,
+}
+
+/// Feeds `trace` through detection and the presence pipeline, calling
+/// `on_activity` whenever the activity to show changes, as the live loop
+/// would tell the Discord actor. Returns the log and activity changes in order.
+pub fn replay(trace: &[TraceLine], mut on_activity: impl FnMut(Option<&Presence>)) -> Vec<ReplayLine> {
+    let mut state = AppState::new();
+    let mut settings = Settings::default();
+    let mut scanner = ProcessScanner::new();
+    let mut detector = Detector::default();
+    let mut requested: Option<Option<Presence>> = None;
+    let mut lines = Vec::new();
+
+    for line in trace {
+        let (at_ms, processes) = match line {
+            TraceLine::Settings(recorded) => {
+                recorded.apply(&mut settings);
+                scanner.set_keywords(&settings.keywords);
+                continue;
+            }
+            TraceLine::Scan { at_ms, processes, .. } => (*at_ms, processes),
+        };
+
+        let seq = state.next_log_seq;
+        let detection = scanner.evaluate(processes, false);
+        detector.step(&mut state, &settings, &detection, at_ms, false);
+        for log in state.logs.iter().filter(|l| l.seq >= seq) {
+            lines.push(ReplayLine { at_ms, text: format!("[{}] {}", log.level.name(), log.message) });
+        }
+
+        let desired = desired_presence(&state, &settings);
+        if requested.as_ref() != Some(&desired) {
+            on_activity(desired.as_ref());
+            // The first update only clears whatever was shown before the run
+            if requested.is_some() || desired.is_some() {
+                let text = match &desired {
+                    Some(p) => format!("activity: {} | {}", p.details, p.state.as_deref().unwrap_or("")),
+                    None => "activity cleared".to_string(),
+                };
+                lines.push(ReplayLine { at_ms, text });
+            }
+            requested = Some(desired);
+        }
+    }
+    lines
+}
+
+impl fmt::Display for ReplayLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = chrono::DateTime::from_timestamp_millis(self.at_ms).unwrap_or_default().with_timezone(&chrono::Local);
+        write!(f, "{}  {}", at.format("%H:%M:%S%.3f"), self.text)
+    }
+}
+
+/// `--replay <file>`: prints what a recorded run did.
+pub fn run_replay(path: &str) -> Result<()> {
+    let trace = load(path)?;
+    let scans: Vec<(i64, u64)> = trace
+        .iter()
+        .filter_map(|line| match line {
+            TraceLine::Scan { at_ms, scan_us, .. } => Some((*at_ms, *scan_us)),
+            TraceLine::Settings(_) => None,
+        })
+        .collect();
+    let lines = replay(&trace, |_| {});
+    for line in &lines {
+        println!("{}", line);
+    }
+
+    let span_ms = match (scans.first(), scans.last()) {
+        (Some(first), Some(last)) => last.0 - first.0,
+        _ => 0,
+    };
+    let slowest_us = scans.iter().map(|s| s.1).max().unwrap_or(0);
+    let updates = lines.iter().filter(|l| l.text.starts_with("activity")).count();
+    println!(
+        "{} scan(s) over {:.1} s, {} activity change(s), slowest scan {:.1} ms",
+        scans.len(),
+        span_ms as f64 / 1000.0,
+        updates,
+        slowest_us as f64 / 1000.0
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: i64 = 1_760_000_000_000;
+    const WINDOW: &str = "MotorStorm: Pacific Rift [BCES00129]";
+
+    fn rpcs3(titles: &[&str]) -> Vec<SeenProcess> {
+        vec![SeenProcess {
+            pid: 4242,
+            name: "rpcs3.exe".to_string(),
+            titles: Some(titles.iter().map(|t| t.to_string()).collect()),
+            access_denied: false,
+        }]
+    }
+
+    /// A race whose window title blips to the emulator's own title for one scan.
+    fn flapping_race(dir: &tempfile::TempDir) -> String {
+        let path = dir.path().join("race.trace").display().to_string();
+        let mut recorder = TraceRecorder::create(&path).unwrap();
+        recorder.record_settings(&Settings::default()).unwrap();
+        let scans: &[(i64, &[&str])] = &[
+            (0, &["RPCS3 0.0.30"]),
+            (3_000, &[WINDOW]),
+            (6_000, &[WINDOW]),
+            (9_000, &["RPCS3 0.0.30"]),
+            (12_000, &[WINDOW]),
+            (15_000, &[WINDOW]),
+        ];
+        for (offset, titles) in scans {
+            recorder.record_scan(T0 + offset, Duration::from_micros(850), &rpcs3(titles)).unwrap();
+        }
+        // Unchanged settings aren't written again
+        recorder.record_settings(&Settings::default()).unwrap();
+        path
+    }
+
+    #[test]
+    fn recorded_traces_replay_deterministically() {
+        let dir = tempfile::tempdir().unwrap();
+        let trace = load(&flapping_race(&dir)).unwrap();
+        assert_eq!(trace.len(), 7);
+
+        let lines = replay(&trace, |_| {});
+        assert_eq!(lines, replay(&trace, |_| {}));
+        let texts: Vec<(i64, &str)> = lines.iter().map(|l| (l.at_ms - T0, l.text.as_str())).collect();
+        assert_eq!(
+            texts,
+            vec![
+                (3_000, "[game] MotorStorm Detected: MotorStorm: Pacific Rift [BCES00129]"),
+                (3_000, "activity: Playing MotorStorm®: Pacific Rift | On RPCS3 Emulator"),
+                (9_000, "[game] Game window no longer active"),
+                (9_000, "[info] Session recorded: 00:00:06"),
+                (9_000, "activity cleared"),
+                (12_000, "[game] MotorStorm Detected: MotorStorm: Pacific Rift [BCES00129]"),
+                (12_000, "activity: Playing MotorStorm®: Pacific Rift | On RPCS3 Emulator"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recorded_settings_apply_from_where_they_appear() {
+        let stricter = Settings { keywords: vec!["bcus98155".to_string()], ..Settings::default() };
+        let trace = vec![
+            TraceLine::Settings(TraceSettings::from_settings(&stricter)),
+            TraceLine::Scan { at_ms: T0, scan_us: 0, processes: rpcs3(&[WINDOW]) },
+            TraceLine::Settings(TraceSettings::from_settings(&Settings::default())),
+            TraceLine::Scan { at_ms: T0 + 3_000, scan_us: 0, processes: rpcs3(&[WINDOW]) },
+        ];
+        let lines = replay(&trace, |_| {});
+        assert_eq!(lines[0].at_ms, T0 + 3_000);
+        assert!(lines[0].text.contains("MotorStorm Detected"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replay_drives_discord_through_the_mock() {
+        use crate::modules::discord::DiscordClient;
+        use crate::modules::discord_actor::{self, DiscordCommand};
+        use crate::modules::mock_ipc::MockDiscord;
+        use parking_lot::Mutex;
+        use std::sync::Arc;
+
+        let mock = MockDiscord::start();
+        let _env = mock.activate();
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        let (discord, task) = discord_actor::spawn(DiscordClient::new(&Settings::default().client_id), app_state);
+        while !discord.status().connected {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let trace = load(&flapping_race(&dir)).unwrap();
+        // The mock is waited on with blocking sleeps
+        let (lines, mock, discord) = tokio::task::spawn_blocking(move || {
+            let mut expected = 0;
+            let lines = replay(&trace, |presence| {
+                discord.send(DiscordCommand::SetActivity(presence.cloned()));
+                // Clearing before anything was shown sends nothing; otherwise wait for
+                // each update, so the actor doesn't fold them into one
+                if presence.is_some() || expected > 0 {
+                    expected += 1;
+                    mock.wait_for_activities(expected, Duration::from_secs(3));
+                }
+            });
+            (lines, mock, discord)
+        })
+        .await
+        .unwrap();
+
+        let activities = mock.activities();
+        assert_eq!(activities.len(), 3);
+        assert_eq!(activities[0]["details"], "Playing MotorStorm®: Pacific Rift");
+        assert_eq!(activities[0]["timestamps"]["start"], (T0 + 3_000) / 1000);
+        assert!(activities[1].is_null());
+        assert_eq!(activities[2]["timestamps"]["start"], (T0 + 12_000) / 1000);
+        assert_eq!(lines.iter().filter(|l| l.text.starts_with("activity")).count(), 3);
+
+        discord_actor::shutdown(&discord, task).await;
+    }
+}
@@ -1,23 +1,323 @@
-use crate::modules::app::{AppState, LogLevel};
+use crate::modules::app::{AppState, LogLevel, LogMessage};
+use crate::modules::config::{ManualOverride, SettingField, Settings};
+use crate::modules::connection::SentActivity;
+use crate::modules::utils::format_duration;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout, Alignment},
+    layout::{Constraint, Direction, Layout, Alignment, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Line, Text},
     widgets::{Block, Borders, Paragraph, Wrap},
     Frame, Terminal,
 };
-use std::{io, sync::Arc};
+use std::{collections::HashSet, io, sync::Arc};
 use parking_lot::Mutex;
 use std::time::Duration;
+use chrono::{Local, TimeZone};
 
 use std::sync::atomic::{AtomicBool, Ordering};
 
+const MOUSE_SCROLL_LINES: usize = 3;
+
+/// Scroll position, filters and search state of the log pane.
+struct LogView {
+    /// Sequence number of the bottom-most visible entry; `None` follows new entries.
+    anchor: Option<u64>,
+    /// Levels hidden by the filter keys.
+    hidden: HashSet<LogLevel>,
+    search: String,
+    search_input: bool,
+    /// Inner height of the log pane at the last draw, used for paging.
+    page_height: usize,
+    /// Screen area of the log pane at the last draw, used for mouse hit-testing.
+    area: Rect,
+}
+
+impl LogView {
+    fn new() -> Self {
+        Self {
+            anchor: None,
+            hidden: HashSet::new(),
+            search: String::new(),
+            search_input: false,
+            page_height: 10,
+            area: Rect::default(),
+        }
+    }
+
+    fn is_visible(&self, log: &LogMessage) -> bool {
+        !self.hidden.contains(&log.level)
+    }
+
+    /// Whether the filter `level` belongs to is showing its entries.
+    fn is_shown(&self, level: &LogLevel) -> bool {
+        !self.hidden.contains(level)
+    }
+
+    fn visible<'a>(&self, state: &'a AppState) -> Vec<&'a LogMessage> {
+        state.logs.iter().filter(|l| self.is_visible(l)).collect()
+    }
+
+    /// Index of the bottom-most visible entry within `visible`.
+    fn bottom_index(&self, visible: &[&LogMessage]) -> Option<usize> {
+        match self.anchor {
+            None => visible.len().checked_sub(1),
+            Some(seq) => match visible.iter().rposition(|l| l.seq <= seq) {
+                Some(i) => Some(i),
+                None if visible.is_empty() => None,
+                None => Some(0),
+            },
+        }
+    }
+
+    fn scroll_up(&mut self, state: &AppState, lines: usize) {
+        let visible = self.visible(state);
+        if let Some(bottom) = self.bottom_index(&visible) {
+            self.anchor = Some(visible[bottom.saturating_sub(lines)].seq);
+        }
+    }
+
+    fn scroll_down(&mut self, state: &AppState, lines: usize) {
+        let visible = self.visible(state);
+        if let Some(bottom) = self.bottom_index(&visible) {
+            let target = bottom + lines;
+            self.anchor = if target >= visible.len() - 1 {
+                None
+            } else {
+                Some(visible[target].seq)
+            };
+        }
+    }
+
+    fn scroll_home(&mut self, state: &AppState) {
+        let visible = self.visible(state);
+        // Keep the first page filled rather than showing a single entry at the bottom
+        let target = self.page_height.saturating_sub(1).min(visible.len().saturating_sub(1));
+        if let Some(log) = visible.get(target) {
+            self.anchor = Some(log.seq);
+        }
+        if target + 1 >= visible.len() {
+            self.anchor = None;
+        }
+    }
+
+    fn matches(&self, log: &LogMessage) -> bool {
+        !self.search.is_empty() && !find_matches(&log.message, &self.search).is_empty()
+    }
+
+    /// Moves the view to the nearest matching entry before (`backwards`) or after the current bottom.
+    fn jump_to_match(&mut self, state: &AppState, backwards: bool, include_current: bool) {
+        let visible = self.visible(state);
+        let Some(bottom) = self.bottom_index(&visible) else { return };
+        let found = if backwards {
+            let end = if include_current { bottom + 1 } else { bottom };
+            visible[..end].iter().rposition(|l| self.matches(l))
+        } else {
+            let start = if include_current { bottom } else { bottom + 1 };
+            visible
+                .iter()
+                .skip(start)
+                .position(|l| self.matches(l))
+                .map(|i| i + start)
+        };
+        if let Some(i) = found {
+            self.anchor = if i + 1 == visible.len() { None } else { Some(visible[i].seq) };
+        }
+    }
+
+    /// Shows or hides the filter `level` belongs to.
+    fn toggle_filter(&mut self, state: &mut AppState, level: LogLevel) {
+        let (label, levels) = filter_of(&level);
+        let show = !self.is_shown(&level);
+        for level in levels {
+            if show {
+                self.hidden.remove(level);
+            } else {
+                self.hidden.insert(level.clone());
+            }
+        }
+        let s = if show { "shown" } else { "hidden" };
+        state.add_log(LogLevel::Info, format!("{} logs {}", label, s));
+    }
+}
+
+/// The filter a level belongs to: its label and the levels it toggles together.
+fn filter_of(level: &LogLevel) -> (&'static str, &'static [LogLevel]) {
+    match level {
+        LogLevel::Info | LogLevel::Success => ("Info", &[LogLevel::Info, LogLevel::Success]),
+        LogLevel::Game => ("Game", &[LogLevel::Game]),
+        LogLevel::Warning | LogLevel::Error => ("Error", &[LogLevel::Warning, LogLevel::Error]),
+    }
+}
+
+/// State of the settings screen; edits go to `draft` until saved.
+struct SettingsView {
+    open: bool,
+    draft: Settings,
+    selected: usize,
+    /// Text being typed for the selected field, if editing.
+    editing: Option<String>,
+    /// Feedback for the last action and whether it was an error.
+    message: Option<(String, bool)>,
+}
+
+impl SettingsView {
+    fn new() -> Self {
+        Self {
+            open: false,
+            draft: Settings::default(),
+            selected: 0,
+            editing: None,
+            message: None,
+        }
+    }
+
+    fn field(&self) -> SettingField {
+        SettingField::ALL[self.selected]
+    }
+
+    fn open(&mut self, state: &AppState) {
+        self.open = true;
+        self.draft = state.settings.clone();
+        self.editing = None;
+        self.message = None;
+    }
+
+    fn handle_key(&mut self, app_state: &Arc<Mutex<AppState>>, code: KeyCode) {
+        if let Some(buffer) = &mut self.editing {
+            match code {
+                KeyCode::Esc => self.editing = None,
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Char(c) => buffer.push(c),
+                KeyCode::Enter => {
+                    let value = buffer.clone();
+                    let field = self.field();
+                    match field.set(&mut self.draft, &value) {
+                        Ok(()) => {
+                            self.editing = None;
+                            self.message = Some((format!("{} updated (W to save)", field.label()), false));
+                        }
+                        Err(e) => self.message = Some((e, true)),
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(SettingField::ALL.len() - 1),
+            KeyCode::Enter => {
+                self.editing = Some(self.field().get(&self.draft));
+                self.message = None;
+            }
+            KeyCode::Char('r') => {
+                let field = self.field();
+                let default = field.get(&Settings::default());
+                // Defaults are always valid
+                let _ = field.set(&mut self.draft, &default);
+                self.message = Some((format!("{} reset to default", field.label()), false));
+            }
+            KeyCode::Char('w') => self.save(app_state),
+            KeyCode::Esc | KeyCode::Char('s') => {
+                self.open = false;
+                let mut state = app_state.lock();
+                if self.draft != state.settings {
+                    state.add_log(LogLevel::Warning, "Unsaved settings changes discarded".to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn save(&mut self, app_state: &Arc<Mutex<AppState>>) {
+        if let Err(e) = self.draft.validate() {
+            self.message = Some((e, true));
+            return;
+        }
+
+        let mut state = app_state.lock();
+        // Applied live even if writing the file fails; the background task logs the changes
+        state.settings = self.draft.clone();
+        match self.draft.save() {
+            Ok(path) => {
+                let msg = format!("Settings saved to {}", path.display());
+                state.add_log(LogLevel::Success, msg.clone());
+                self.message = Some((msg, false));
+            }
+            Err(e) => {
+                let msg = format!("Settings applied but not saved: {}", e);
+                state.add_log(LogLevel::Error, msg.clone());
+                self.message = Some((msg, true));
+            }
+        }
+    }
+}
+
+const OVERRIDE_STEPS: &[&str] = &["Details", "State (optional)", "Show timer? (y/n)"];
+
+/// Step-by-step prompt for pinning a manual activity.
+struct OverridePrompt {
+    step: usize,
+    details: String,
+    state: String,
+    error: Option<String>,
+}
+
+impl OverridePrompt {
+    fn new(current: Option<&ManualOverride>) -> Self {
+        Self {
+            step: 0,
+            details: current.map(|o| o.details.clone()).unwrap_or_default(),
+            state: current.and_then(|o| o.state.clone()).unwrap_or_default(),
+            error: None,
+        }
+    }
+
+    /// Handles a key press; returns `false` once the prompt is finished or cancelled.
+    fn handle_key(&mut self, app_state: &Arc<Mutex<AppState>>, code: KeyCode) -> bool {
+        let buffer = match self.step {
+            0 => Some(&mut self.details),
+            1 => Some(&mut self.state),
+            _ => None,
+        };
+        match (code, buffer) {
+            (KeyCode::Esc, _) => return false,
+            (KeyCode::Backspace, Some(buffer)) => {
+                buffer.pop();
+            }
+            (KeyCode::Char(c), Some(buffer)) => buffer.push(c),
+            (KeyCode::Enter, Some(_)) => self.step += 1,
+            (KeyCode::Char('y' | 'Y'), None) => return self.finish(app_state, true),
+            (KeyCode::Char('n' | 'N') | KeyCode::Enter, None) => return self.finish(app_state, false),
+            _ => {}
+        }
+        true
+    }
+
+    fn finish(&mut self, app_state: &Arc<Mutex<AppState>>, timer: bool) -> bool {
+        match ManualOverride::new(&self.details, Some(&self.state), timer) {
+            Ok(manual) => {
+                app_state.lock().set_override(Some(manual));
+                false
+            }
+            Err(e) => {
+                self.error = Some(e);
+                self.step = 0;
+                true
+            }
+        }
+    }
+}
+
 pub fn run_tui(app_state: Arc<Mutex<AppState>>, running: Arc<AtomicBool>) -> anyhow::Result<()> {
     // Setup terminal
     enable_raw_mode()?;
@@ -49,12 +349,30 @@ fn run_app<B: Backend>(
     app_state: Arc<Mutex<AppState>>,
     running: Arc<AtomicBool>,
 ) -> io::Result<()> {
+    let mut view = LogView::new();
+    let mut settings = SettingsView::new();
+    let mut prompt: Option<OverridePrompt> = None;
+
     while running.load(Ordering::Relaxed) {
-        terminal.draw(|f| ui(f, &app_state))?;
+        terminal.draw(|f| ui(f, &app_state, &mut view, &settings, prompt.as_ref()))?;
 
         if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == event::KeyEventKind::Press {
+            match event::read()? {
+                Event::Key(key) if key.kind == event::KeyEventKind::Press => {
+                    if let Some(p) = &mut prompt {
+                        if !p.handle_key(&app_state, key.code) {
+                            prompt = None;
+                        }
+                        continue;
+                    }
+                    if settings.open {
+                        settings.handle_key(&app_state, key.code);
+                        continue;
+                    }
+                    if view.search_input {
+                        handle_search_key(&mut view, &app_state, key.code);
+                        continue;
+                    }
                     match key.code {
                         KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
                         KeyCode::Char('d') => {
@@ -67,17 +385,162 @@ fn run_app<B: Backend>(
                              let mut state = app_state.lock();
                              state.logs.clear();
                              state.add_log(LogLevel::Info, "Logs cleared".to_string());
+                             view.anchor = None;
+                        }
+                        KeyCode::Char('s') => settings.open(&app_state.lock()),
+                        KeyCode::Char('o') => {
+                            prompt = Some(OverridePrompt::new(app_state.lock().manual_override.as_ref()));
+                        }
+                        KeyCode::Char('x') => app_state.lock().set_override(None),
+                        KeyCode::Char('p') => {
+                            let mut state = app_state.lock();
+                            let paused = !state.presence_paused;
+                            state.set_paused(paused);
+                        }
+                        KeyCode::Char('i') => view.toggle_filter(&mut app_state.lock(), LogLevel::Info),
+                        KeyCode::Char('g') => view.toggle_filter(&mut app_state.lock(), LogLevel::Game),
+                        KeyCode::Char('e') => view.toggle_filter(&mut app_state.lock(), LogLevel::Error),
+                        KeyCode::Char('/') => {
+                            view.search.clear();
+                            view.search_input = true;
                         }
+                        KeyCode::Char('n') => view.jump_to_match(&app_state.lock(), true, false),
+                        KeyCode::Char('N') => view.jump_to_match(&app_state.lock(), false, false),
+                        KeyCode::Up => view.scroll_up(&app_state.lock(), 1),
+                        KeyCode::Down => view.scroll_down(&app_state.lock(), 1),
+                        KeyCode::PageUp => view.scroll_up(&app_state.lock(), view.page_height),
+                        KeyCode::PageDown => view.scroll_down(&app_state.lock(), view.page_height),
+                        KeyCode::Home => view.scroll_home(&app_state.lock()),
+                        KeyCode::End => view.anchor = None,
                         _ => {}
                     }
                 }
+                Event::Mouse(mouse) if !settings.open && prompt.is_none() => {
+                    let in_logs = mouse.column >= view.area.x
+                        && mouse.column < view.area.x + view.area.width
+                        && mouse.row >= view.area.y
+                        && mouse.row < view.area.y + view.area.height;
+                    if in_logs {
+                        match mouse.kind {
+                            MouseEventKind::ScrollUp => view.scroll_up(&app_state.lock(), MOUSE_SCROLL_LINES),
+                            MouseEventKind::ScrollDown => view.scroll_down(&app_state.lock(), MOUSE_SCROLL_LINES),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
             }
         }
     }
     Ok(())
 }
 
-fn ui(f: &mut Frame, app_state: &Arc<Mutex<AppState>>) {
+fn handle_search_key(view: &mut LogView, app_state: &Arc<Mutex<AppState>>, code: KeyCode) {
+    match code {
+        KeyCode::Esc => {
+            view.search.clear();
+            view.search_input = false;
+        }
+        KeyCode::Enter => view.search_input = false,
+        KeyCode::Backspace => {
+            view.search.pop();
+        }
+        KeyCode::Char(c) => {
+            view.search.push(c);
+            // Incremental: jump to the newest match as the query grows
+            view.anchor = None;
+            view.jump_to_match(&app_state.lock(), true, true);
+        }
+        _ => {}
+    }
+}
+
+/// Byte ranges of case-insensitive occurrences of `needle` in `haystack`.
+fn find_matches(haystack: &str, needle: &str) -> Vec<(usize, usize)> {
+    let needle: Vec<char> = needle.chars().flat_map(char::to_lowercase).collect();
+    let mut ranges = Vec::new();
+    if needle.is_empty() {
+        return ranges;
+    }
+
+    let mut start = 0;
+    while start < haystack.len() {
+        let mut chars = haystack[start..].char_indices().flat_map(|(i, c)| c.to_lowercase().map(move |l| (i, c, l)));
+        let mut end = None;
+        for n in &needle {
+            match chars.next() {
+                Some((i, c, l)) if l == *n => end = Some(start + i + c.len_utf8()),
+                _ => {
+                    end = None;
+                    break;
+                }
+            }
+        }
+        let step = haystack[start..].chars().next().map_or(1, char::len_utf8);
+        match end {
+            Some(end) => {
+                ranges.push((start, end));
+                start = end;
+            }
+            None => start += step,
+        }
+    }
+    ranges
+}
+
+fn highlight_line<'a>(message: &'a str, search: &str, style: Style) -> Vec<Span<'a>> {
+    let highlight = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for (start, end) in find_matches(message, search) {
+        if start > pos {
+            spans.push(Span::styled(&message[pos..start], style));
+        }
+        spans.push(Span::styled(&message[start..end], highlight));
+        pos = end;
+    }
+    if pos < message.len() {
+        spans.push(Span::styled(&message[pos..], style));
+    }
+    spans
+}
+
+/// Formats a Unix timestamp as local `HH:MM:SS`.
+fn clock_time(timestamp: i64) -> String {
+    Local.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// One-line summary of what Discord was last told to show.
+fn describe_sent_activity(sent: &SentActivity) -> String {
+    let Some(activity) = &sent.activity else {
+        return format!("{} cleared", clock_time(sent.sent_at));
+    };
+    let mut parts = vec![format!(
+        "{} {} / {}",
+        clock_time(sent.sent_at),
+        activity.details.as_deref().unwrap_or("-"),
+        activity.state.as_deref().unwrap_or("-")
+    )];
+    if let Some(assets) = &activity.assets {
+        let images: Vec<_> = [&assets.large_image, &assets.small_image].into_iter().flatten().cloned().collect();
+        parts.push(format!("assets: {}", images.join(", ")));
+    }
+    if let Some(start) = activity.timestamps.as_ref().and_then(|t| t.start) {
+        parts.push(format!("timer since {}", clock_time(start)));
+    }
+    parts.join(" | ")
+}
+
+fn ui(
+    f: &mut Frame,
+    app_state: &Arc<Mutex<AppState>>,
+    view: &mut LogView,
+    settings: &SettingsView,
+    prompt: Option<&OverridePrompt>,
+) {
     let state = app_state.lock();
 
     let chunks = Layout::default()
@@ -86,7 +549,7 @@ fn ui(f: &mut Frame, app_state: &Arc<Mutex<AppState>>) {
         .constraints(
             [
                 Constraint::Length(3), // Header
-                Constraint::Length(10), // Status Dashboard
+                Constraint::Length(13), // Status Dashboard
                 Constraint::Min(10),   // Logs
                 Constraint::Length(3), // Footer
             ]
@@ -121,10 +584,30 @@ fn ui(f: &mut Frame, app_state: &Arc<Mutex<AppState>>) {
         Span::styled("NOT DETECTED", Style::default().fg(Color::Red))
     };
 
-    let discord_status = if state.discord_connected {
+    let discord = &state.discord;
+    let mut discord_status = vec![if discord.connected {
         Span::styled("CONNECTED", Style::default().fg(Color::Green))
     } else {
         Span::styled("DISCONNECTED", Style::default().fg(Color::Red))
+    }];
+    if let Some(user) = &discord.user {
+        let flavor = discord.flavor.map(|f| f.to_string()).unwrap_or_default();
+        discord_status.push(Span::raw(format!(" as {} ({})", user.display_name(), flavor)));
+    }
+
+    let endpoint = match &discord.endpoint {
+        Some(e) => Span::raw(format!("{} ({})", e.path, e.packaging)),
+        None => Span::styled("N/A", Style::default().fg(Color::DarkGray)),
+    };
+
+    let last_sent = match &discord.last_activity {
+        Some(sent) => Span::raw(describe_sent_activity(sent)),
+        None => Span::styled("Nothing sent yet", Style::default().fg(Color::DarkGray)),
+    };
+
+    let last_error = match &discord.last_error {
+        Some(e) => Span::styled(format!("{} {}", clock_time(e.at), e.message), Style::default().fg(Color::Red)),
+        None => Span::styled("None", Style::default().fg(Color::DarkGray)),
     };
 
     let window_info = if let Some(w) = &state.matched_window {
@@ -133,26 +616,46 @@ fn ui(f: &mut Frame, app_state: &Arc<Mutex<AppState>>) {
         Span::styled("N/A", Style::default().fg(Color::DarkGray))
     };
 
-     let uptime = if let Some(start) = state.start_timestamp {
-        let now = chrono::Utc::now().timestamp();
-        let diff = now - start;
-        let h = diff / 3600;
-        let m = (diff % 3600) / 60;
-        let s = diff % 60;
-        format!("{:02}:{:02}:{:02}", h, m, s)
-    } else {
-        "--:--:--".to_string()
+    let uptime = match state.start_timestamp {
+        Some(start) => format_duration(chrono::Utc::now().timestamp() - start),
+        None => "--:--:--".to_string(),
     };
 
     let ram_mb = state.ram_usage as f32 / 1024.0 / 1024.0;
 
+    let last_session = match state.sessions.back() {
+        Some(record) => Span::raw(format!(
+            "{} ({}) | {} recorded",
+            format_duration(record.duration_secs()),
+            record.window.as_deref().unwrap_or("N/A"),
+            state.sessions.len()
+        )),
+        None => Span::styled("N/A", Style::default().fg(Color::DarkGray)),
+    };
+
+    let presence_mode = if state.presence_paused {
+        Span::styled("PAUSED (privacy mode, nothing is broadcast)", Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD))
+    } else if let Some(manual) = &state.manual_override {
+        let text = match &manual.state {
+            Some(s) => format!("MANUAL OVERRIDE: {} / {}", manual.details, s),
+            None => format!("MANUAL OVERRIDE: {}", manual.details),
+        };
+        Span::styled(text, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+    } else {
+        Span::styled("Automatic", Style::default().fg(Color::Green))
+    };
+
     let status_text = vec![
         Line::from(vec![Span::raw("Game Status:      "), game_status]),
-        Line::from(vec![Span::raw("Discord Status:   "), discord_status]),
+        Line::from([vec![Span::raw("Discord Status:   ")], discord_status].concat()),
+        Line::from(vec![Span::raw("Endpoint:         "), endpoint]),
         Line::from(vec![Span::raw("Current Session:  "), Span::raw(uptime)]),
         Line::from(vec![Span::raw("App Usage:        "), Span::raw(format!("CPU: {:.1}% | RAM: {:.2} MB", state.cpu_usage, ram_mb))]),
         Line::from(vec![Span::raw("Detected Window:  "), window_info]),
-        Line::from(vec![]),
+        Line::from(vec![Span::raw("Presence Mode:    "), presence_mode]),
+        Line::from(vec![Span::raw("Last Sent:        "), last_sent]),
+        Line::from(vec![Span::raw("Discord Error:    "), last_error]),
+        Line::from(vec![Span::raw("Last Session:     "), last_session]),
         Line::from(vec![Span::styled("Monitoring RPCS3 behavior...", Style::default().fg(Color::Gray))]),
     ];
 
@@ -161,58 +664,318 @@ fn ui(f: &mut Frame, app_state: &Arc<Mutex<AppState>>) {
         .wrap(Wrap { trim: true });
     f.render_widget(status_p, chunks[1]);
 
-    // Logs
-    let logs_block = Block::default()
-        .title(" Logs ")
-        .borders(Borders::ALL);
-    
-    let mut log_lines = Vec::new();
-    for log in &state.logs {
-        let style = match log.level {
-            LogLevel::Info => Style::default().fg(Color::Cyan),
-            LogLevel::Success => Style::default().fg(Color::Green),
-            LogLevel::Warning => Style::default().fg(Color::Yellow),
-            LogLevel::Error => Style::default().fg(Color::Red),
-            LogLevel::Game => Style::default().fg(Color::Magenta),
-        };
-        let icon = match log.level {
-             LogLevel::Info => "ℹ",
-            LogLevel::Success => "✓",
-            LogLevel::Warning => "⚠",
-            LogLevel::Error => "✗",
-            LogLevel::Game => "🎮",
-        };
-        log_lines.push(Line::from(vec![
-            Span::styled(format!("{} ", icon), style),
-            Span::raw(&log.message),
-        ]));
+    if let Some(prompt) = prompt {
+        render_override_prompt(f, chunks[2], prompt);
+    } else if settings.open {
+        render_settings(f, chunks[2], &state, settings);
+    } else {
+        render_logs(f, chunks[2], &state, view);
     }
-    
-    // Reverse logs to show newest at bottom if we want normal log behavior, 
-    // but usually in TUI we want scrolling. Ratatui renders top-down. 
-    // Let's just show them as is, but maybe scroll to bottom if too many?
-    // For simplicity, we just take the last N that fit.
-    
-    let logs_p = Paragraph::new(log_lines)
-        .block(logs_block)
-        .wrap(Wrap { trim: false });
-    f.render_widget(logs_p, chunks[2]);
 
     // Footer
-    let footer_text = vec![
-        Line::from(vec![
-            Span::raw("Press "),
-            Span::styled("Q", Style::default().add_modifier(Modifier::BOLD)),
-            Span::raw(" to Quit | "),
-            Span::styled("D", Style::default().add_modifier(Modifier::BOLD)),
-             Span::raw(" Toggle Debug | "),
-             Span::styled("C", Style::default().add_modifier(Modifier::BOLD)),
-             Span::raw(" Clear Logs"),
-        ])
-    ];
+    let key = |k: &'static str| Span::styled(k, Style::default().add_modifier(Modifier::BOLD));
+    let footer_text = if prompt.is_some() {
+        vec![Line::from(vec![
+            key("Enter"),
+            Span::raw(" Next | "),
+            key("Esc"),
+            Span::raw(" Cancel"),
+        ])]
+    } else if settings.open {
+        vec![Line::from(vec![
+            key("↑/↓"),
+            Span::raw(" Select | "),
+            key("Enter"),
+            Span::raw(" Edit/Confirm | "),
+            key("R"),
+            Span::raw(" Reset Field | "),
+            key("W"),
+            Span::raw(" Save & Apply | "),
+            key("Esc"),
+            Span::raw(" Back"),
+        ])]
+    } else {
+        vec![
+            Line::from(vec![
+                Span::raw("Press "),
+                key("Q"),
+                Span::raw(" to Quit | "),
+                key("D"),
+                Span::raw(" Toggle Debug | "),
+                key("C"),
+                Span::raw(" Clear Logs | "),
+                key("S"),
+                Span::raw(" Settings | "),
+                key("O"),
+                Span::raw(" Manual Presence | "),
+                key("X"),
+                Span::raw(" Clear Manual | "),
+                key("P"),
+                Span::raw(" Pause/Resume"),
+            ]),
+            Line::from(vec![
+                key("I/G/E"),
+                Span::raw(" Filter | "),
+                key("/"),
+                Span::raw(" Search ("),
+                key("n/N"),
+                Span::raw(") | "),
+                key("PgUp/PgDn/Home/End"),
+                Span::raw(" Scroll"),
+            ]),
+        ]
+    };
     let footer_p = Paragraph::new(footer_text)
         .block(Block::default().borders(Borders::TOP))
         .alignment(Alignment::Center);
 
     f.render_widget(footer_p, chunks[3]);
 }
+
+fn render_override_prompt(f: &mut Frame, area: Rect, prompt: &OverridePrompt) {
+    let block = Block::default()
+        .title(" Manual Presence ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Yellow));
+
+    let values = [prompt.details.as_str(), prompt.state.as_str(), ""];
+    let mut lines = Vec::new();
+    for (i, label) in OVERRIDE_STEPS.iter().enumerate() {
+        let value = if i == prompt.step {
+            Span::styled(format!("{}_", values[i]), Style::default().fg(Color::Black).bg(Color::Cyan))
+        } else {
+            Span::raw(values[i].to_string())
+        };
+        lines.push(Line::from(vec![Span::raw(format!("{:20}", label)), value]));
+    }
+    lines.push(Line::from(vec![]));
+    if let Some(e) = &prompt.error {
+        lines.push(Line::from(Span::styled(e.clone(), Style::default().fg(Color::Red))));
+    }
+    lines.push(Line::from(Span::styled(
+        "The activity replaces automatic detection until cleared with X.",
+        Style::default().fg(Color::DarkGray),
+    )));
+
+    f.render_widget(Paragraph::new(lines).block(block).wrap(Wrap { trim: false }), area);
+}
+
+fn render_settings(f: &mut Frame, area: Rect, state: &AppState, settings: &SettingsView) {
+    let block = Block::default()
+        .title(" Settings ")
+        .borders(Borders::ALL);
+
+    let label_width = SettingField::ALL.iter().map(|f| f.label().len()).max().unwrap_or(0) + 2;
+    let mut lines = Vec::new();
+    for (i, field) in SettingField::ALL.iter().enumerate() {
+        let selected = i == settings.selected;
+        let marker = if selected { "> " } else { "  " };
+        let label_style = if selected {
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+
+        let mut spans = vec![
+            Span::styled(marker, label_style),
+            Span::styled(format!("{:width$}", field.label(), width = label_width), label_style),
+        ];
+        match (&settings.editing, selected) {
+            (Some(buffer), true) => {
+                spans.push(Span::styled(format!("{}_", buffer), Style::default().fg(Color::Black).bg(Color::Cyan)));
+            }
+            _ => {
+                spans.push(Span::raw(field.get(&settings.draft)));
+                if field.get(&settings.draft) != field.get(&state.settings) {
+                    spans.push(Span::styled("  (unsaved)", Style::default().fg(Color::Yellow)));
+                }
+            }
+        }
+        lines.push(Line::from(spans));
+    }
+
+    lines.push(Line::from(vec![]));
+    if let Some((msg, is_error)) = &settings.message {
+        let color = if *is_error { Color::Red } else { Color::Green };
+        lines.push(Line::from(Span::styled(msg.clone(), Style::default().fg(color))));
+    }
+    let path = Settings::path().map_or("unavailable".to_string(), |p| p.display().to_string());
+    lines.push(Line::from(Span::styled(format!("Config file: {}", path), Style::default().fg(Color::DarkGray))));
+
+    // Keep the selected field in view, leaving room for the message and path below
+    let visible = area.height.saturating_sub(2).saturating_sub(3) as usize;
+    let offset = (settings.selected + 1).saturating_sub(visible.max(1)) as u16;
+    let p = Paragraph::new(lines)
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((offset, 0));
+    f.render_widget(p, area);
+}
+
+fn render_logs(f: &mut Frame, area: Rect, state: &AppState, view: &mut LogView) {
+    let visible = view.visible(state);
+    let bottom = view.bottom_index(&visible);
+
+    let mut filters = Vec::new();
+    for level in [LogLevel::Info, LogLevel::Game, LogLevel::Error] {
+        if view.is_shown(&level) {
+            filters.push(filter_of(&level).0);
+        }
+    }
+    let mut logs_title = format!(
+        " Logs [{}] [{}] ",
+        if view.anchor.is_none() { "FOLLOW" } else { "SCROLLED" },
+        if filters.is_empty() { "none".to_string() } else { filters.join(" ") }
+    );
+    if view.search_input || !view.search.is_empty() {
+        let count = visible.iter().filter(|l| view.matches(l)).count();
+        let cursor = if view.search_input { "_" } else { "" };
+        logs_title.push_str(&format!("/{}{} ({} matches) ", view.search, cursor, count));
+    }
+
+    let logs_block = Block::default()
+        .title(logs_title)
+        .borders(Borders::ALL);
+
+    view.area = area;
+    let inner = logs_block.inner(area);
+    view.page_height = (inner.height as usize).max(1);
+    let width = (inner.width as usize).max(1);
+
+    // Walk backwards from the anchored entry until the pane is full, accounting for wrapping
+    let mut log_lines = Vec::new();
+    let mut used = 0;
+    if let Some(bottom) = bottom {
+        for log in visible[..=bottom].iter().rev() {
+            let style = match log.level {
+                LogLevel::Info => Style::default().fg(Color::Cyan),
+                LogLevel::Success => Style::default().fg(Color::Green),
+                LogLevel::Warning => Style::default().fg(Color::Yellow),
+                LogLevel::Error => Style::default().fg(Color::Red),
+                LogLevel::Game => Style::default().fg(Color::Magenta),
+            };
+            let icon = match log.level {
+                 LogLevel::Info => "ℹ",
+                LogLevel::Success => "✓",
+                LogLevel::Warning => "⚠",
+                LogLevel::Error => "✗",
+                LogLevel::Game => "🎮",
+            };
+            let mut spans = vec![Span::styled(format!("{} ", icon), style)];
+            spans.extend(highlight_line(&log.message, &view.search, Style::default()));
+            let line = Line::from(spans);
+
+            let height = line.width().max(1).div_ceil(width);
+            if used + height > view.page_height && !log_lines.is_empty() {
+                break;
+            }
+            used += height;
+            log_lines.push(line);
+        }
+    }
+    log_lines.reverse();
+
+    // The oldest line may not fit entirely; scroll so the newest stays on screen
+    let overflow = used.saturating_sub(view.page_height) as u16;
+    let logs_p = Paragraph::new(log_lines)
+        .block(logs_block)
+        .wrap(Wrap { trim: false })
+        .scroll((overflow, 0));
+    f.render_widget(logs_p, area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twenty entries, seq 0-19: every fifth is a game entry mentioning a lap.
+    fn logs() -> AppState {
+        let mut state = AppState::new();
+        for i in 0..20 {
+            match i % 5 {
+                0 => state.add_log(LogLevel::Game, format!("Lap {}", i)),
+                4 => state.add_log(LogLevel::Error, format!("Error {}", i)),
+                _ => state.add_log(LogLevel::Info, format!("Entry {}", i)),
+            }
+        }
+        state
+    }
+
+    fn view(page_height: usize) -> LogView {
+        LogView { page_height, ..LogView::new() }
+    }
+
+    #[test]
+    fn scrolling_clamps_and_returns_to_following() {
+        let state = logs();
+        let mut view = view(5);
+
+        view.scroll_up(&state, 3);
+        assert_eq!(view.anchor, Some(16));
+        view.scroll_up(&state, 100);
+        assert_eq!(view.anchor, Some(0));
+        view.scroll_down(&state, 2);
+        assert_eq!(view.anchor, Some(2));
+        // Reaching the newest entry follows the tail again
+        view.scroll_down(&state, 100);
+        assert_eq!(view.anchor, None);
+
+        view.scroll_home(&state);
+        assert_eq!(view.anchor, Some(4));
+
+        // A page taller than the log just follows
+        let mut tall = LogView { page_height: 50, ..LogView::new() };
+        tall.scroll_home(&state);
+        assert_eq!(tall.anchor, None);
+        assert_eq!(LogView::new().bottom_index(&[]), None);
+    }
+
+    #[test]
+    fn filters_hide_whole_groups_and_keep_the_anchor_in_range() {
+        let mut state = logs();
+        let mut view = view(5);
+        view.anchor = Some(15);
+
+        view.toggle_filter(&mut state, LogLevel::Game);
+        assert!(!view.is_shown(&LogLevel::Game));
+        let visible = view.visible(&state);
+        assert!(visible.iter().all(|l| l.level != LogLevel::Game));
+        // The anchored entry is hidden, so the view settles on the one before it
+        assert_eq!(visible[view.bottom_index(&visible).unwrap()].seq, 14);
+
+        view.toggle_filter(&mut state, LogLevel::Error);
+        assert!(view.hidden.contains(&LogLevel::Warning) && view.hidden.contains(&LogLevel::Error));
+        view.toggle_filter(&mut state, LogLevel::Warning);
+        assert!(view.is_shown(&LogLevel::Error) && view.is_shown(&LogLevel::Warning));
+
+        let toggles: Vec<_> = state.logs.iter().rev().take(3).map(|l| l.message.clone()).collect();
+        assert_eq!(toggles, vec!["Error logs shown", "Error logs hidden", "Game logs hidden"]);
+    }
+
+    #[test]
+    fn search_jumps_between_matches() {
+        let state = logs();
+        let mut view = view(5);
+        view.search = "lap".to_string();
+
+        let matches: Vec<_> = view.visible(&state).into_iter().filter(|l| view.matches(l)).map(|l| l.seq).collect();
+        assert_eq!(matches, vec![0, 5, 10, 15]);
+
+        view.jump_to_match(&state, true, true);
+        assert_eq!(view.anchor, Some(15));
+        view.jump_to_match(&state, true, false);
+        assert_eq!(view.anchor, Some(10));
+        view.jump_to_match(&state, true, true);
+        assert_eq!(view.anchor, Some(10));
+        view.jump_to_match(&state, false, false);
+        assert_eq!(view.anchor, Some(15));
+        // Nothing further: stays put
+        view.jump_to_match(&state, false, false);
+        assert_eq!(view.anchor, Some(15));
+
+        view.search = "entry".to_string();
+        assert!(view.matches(&state.logs[1]) && !view.matches(&state.logs[0]));
+        view.search.clear();
+        assert!(!view.matches(&state.logs[1]));
+    }
+}
@@ -1,104 +1,402 @@
-use crate::modules::app::{AppState, LogLevel};
-use crate::modules::config::{PROCESS_CHECK_INTERVAL_MS, START_COOLDOWN_MS, STOP_COOLDOWN_MS};
-use crate::modules::discord::DiscordClient;
-use crate::modules::process::ProcessScanner;
+use crate::modules::admin::{self, ElevationWatch};
+use crate::modules::app::{AppState, LogLevel, SessionRecord};
+use crate::modules::api::{self, ApiServer};
+use crate::modules::assets;
+use crate::modules::config::{ElevationPolicy, Settings, DEBUG_LOG_INTERVAL_MS, ELEVATION_GRACE_MS};
+use crate::modules::discord::Presence;
+use crate::modules::discord_actor::{DiscordCommand, DiscordHandle};
+use crate::modules::events::EventKind;
+use crate::modules::process::{Detection, ProcessScanner, ScanReport};
+use crate::modules::trace::TraceRecorder;
+use crate::modules::script::{self, PresenceScript};
 use chrono::Utc;
 use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::time::{sleep, Duration};
 
-pub async fn run_background_tasks(app_state: Arc<Mutex<AppState>>) {
-    let mut discord = DiscordClient::new();
+/// Rate-limits repetitive debug output: a key is let through when its content
+/// changed or when `DEBUG_LOG_INTERVAL_MS` has passed since it was last shown.
+#[derive(Default)]
+struct DebugThrottle {
+    last: HashMap<&'static str, (Instant, u64)>,
+}
+
+impl DebugThrottle {
+    fn allow(&mut self, key: &'static str, content: &impl Hash) -> bool {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let signature = hasher.finish();
+        let now = Instant::now();
+
+        if let Some((at, last_sig)) = self.last.get(key) {
+            let elapsed = now.duration_since(*at).as_millis() as u64;
+            if *last_sig == signature && elapsed < DEBUG_LOG_INTERVAL_MS {
+                return false;
+            }
+        }
+        self.last.insert(key, (now, signature));
+        true
+    }
+}
+
+fn log_scan_report(state: &mut AppState, report: &ScanReport) {
+    if report.candidates.is_empty() {
+        state.add_log(LogLevel::Info, format!("[debug] Scan took {:?}: no emulator process found", report.duration));
+        return;
+    }
+
+    state.add_log(LogLevel::Info, format!(
+        "[debug] Scan took {:?}: {} emulator candidate(s)",
+        report.duration,
+        report.candidates.len()
+    ));
+    for candidate in &report.candidates {
+        match &candidate.titles {
+            None if candidate.access_denied => state.add_log(LogLevel::Info, format!(
+                "[debug]  pid {} ({}): access to window titles denied",
+                candidate.pid, candidate.name
+            )),
+            None => state.add_log(LogLevel::Info, format!(
+                "[debug]  pid {} ({}): window titles unavailable on this platform",
+                candidate.pid, candidate.name
+            )),
+            Some(titles) if titles.is_empty() => state.add_log(LogLevel::Info, format!(
+                "[debug]  pid {} ({}): no visible windows",
+                candidate.pid, candidate.name
+            )),
+            Some(titles) => {
+                for check in titles {
+                    let verdict = match &check.matched_keyword {
+                        Some(kw) => format!("matched \"{}\"", kw),
+                        None => "rejected, no keyword matched".to_string(),
+                    };
+                    state.add_log(LogLevel::Info, format!(
+                        "[debug]  pid {} ({}): \"{}\" {}",
+                        candidate.pid, candidate.name, check.title, verdict
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Formats seconds as `HH:MM:SS`.
+pub fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    format!("{:02}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+/// Applies settings edited in the TUI, logging each changed value.
+fn apply_settings(
+    app_state: &Arc<Mutex<AppState>>,
+    current: &mut Settings,
+    updated: Settings,
+    scanner: &mut ProcessScanner,
+    discord: &DiscordHandle,
+    api_server: &mut Option<ApiServer>,
+) {
+    let changes = current.diff(&updated);
+    {
+        let mut state = app_state.lock();
+        for change in &changes {
+            state.add_log(LogLevel::Info, format!("Setting changed: {}", change));
+        }
+    }
+
+    scanner.set_keywords(&updated.keywords);
+
+    if updated.preferred_endpoint != current.preferred_endpoint {
+        let preferred = Some(updated.preferred_endpoint.as_str()).filter(|p| !p.is_empty());
+        discord.send(DiscordCommand::SetPreferredEndpoint(preferred.map(str::to_string)));
+    }
+
+    if updated.activity_rate_limit != current.activity_rate_limit
+        || updated.activity_rate_window_ms != current.activity_rate_window_ms
+    {
+        discord.send(DiscordCommand::SetRateLimit {
+            max: updated.activity_rate_limit,
+            window: Duration::from_millis(updated.activity_rate_window_ms),
+        });
+    }
+
+    if updated.api_enabled != current.api_enabled || updated.api_port != current.api_port {
+        // Drop the old server first so the port is free to rebind
+        *api_server = None;
+        *api_server = start_api(app_state, &updated);
+    }
+
+    if updated.client_id != current.client_id || updated.assets_url != current.assets_url {
+        assets::spawn_check(app_state.clone(), updated.clone());
+    }
+
+    if updated.client_id != current.client_id {
+        discord.send(DiscordCommand::SetClientId(updated.client_id.clone()));
+        app_state.lock().add_log(LogLevel::Info, "Client ID changed, reconnecting to Discord".to_string());
+    }
+
+    *current = updated;
+}
+
+/// Starts the local HTTP API when enabled, logging where it listens.
+fn start_api(app_state: &Arc<Mutex<AppState>>, settings: &Settings) -> Option<ApiServer> {
+    if !settings.api_enabled {
+        return None;
+    }
+    match api::start(app_state.clone(), settings.api_port) {
+        Ok(server) => {
+            app_state.lock().add_log(LogLevel::Info, format!("HTTP API listening on http://127.0.0.1:{}", server.port));
+            Some(server)
+        }
+        Err(e) => {
+            app_state.lock().add_log(LogLevel::Error, format!("Failed to start HTTP API on port {}: {}", settings.api_port, e));
+            None
+        }
+    }
+}
+
+/// The activity that should currently be shown: a manual override wins over
+/// automatic detection, and nothing is shown when no game is running.
+pub fn desired_presence(state: &AppState, settings: &Settings) -> Option<Presence> {
+    if let Some(manual) = &state.manual_override {
+        return Some(Presence {
+            details: manual.details.clone(),
+            state: manual.state.clone(),
+            start_time: manual.timer_start,
+        });
+    }
+    if !state.game_running {
+        return None;
+    }
+    let window = state.matched_window.as_deref();
+    Some(Presence {
+        details: Settings::render(&settings.details_template, window),
+        state: Some(Settings::render(&settings.state_template, window)),
+        start_time: state.start_timestamp,
+    })
+}
+
+/// The detection state machine: turns scan results into session starts, stops
+/// and window changes. Shared by the live loop and trace replay, so it takes
+/// the time of the scan rather than reading the clock.
+#[derive(Default)]
+pub struct Detector {
+    last_start_time: i64,
+    last_stop_time: i64,
+    throttle: DebugThrottle,
+}
+
+impl Detector {
+    pub fn step(&mut self, state: &mut AppState, settings: &Settings, detection: &Detection, now_ms: i64, debug_mode: bool) {
+        let matched_title = detection.matched_title.clone();
+        if detection.found_game && !state.game_running {
+            if now_ms - self.last_start_time > settings.start_cooldown_ms {
+                let msg = format!("MotorStorm Detected: {}", matched_title.clone().unwrap_or_default());
+                state.add_log(LogLevel::Game, msg);
+                let start = now_ms / 1000;
+                state.game_running = true;
+                state.start_timestamp = Some(start);
+                state.game_pid = detection.matched_pid;
+                state.events.publish(EventKind::SessionStarted {
+                    window_title: matched_title.clone(),
+                    start,
+                    pid: state.game_pid,
+                });
+                state.matched_window = matched_title;
+                self.last_start_time = now_ms;
+            } else if debug_mode && self.throttle.allow("start_cooldown", &self.last_start_time) {
+                state.add_log(LogLevel::Info, "[debug] Game detected but start cooldown still active".to_string());
+            }
+        } else if state.game_running && !detection.found_game && now_ms - self.last_stop_time > settings.stop_cooldown_ms {
+            if !detection.found_rpcs3 {
+                state.add_log(LogLevel::Game, "RPCS3 process closed".to_string());
+            } else {
+                state.add_log(LogLevel::Game, "Game window no longer active".to_string());
+            }
+            if let Some(start) = state.start_timestamp {
+                let record = SessionRecord {
+                    window: state.matched_window.clone(),
+                    start_timestamp: start,
+                    end_timestamp: now_ms / 1000,
+                };
+                state.add_log(LogLevel::Info, format!("Session recorded: {}", format_duration(record.duration_secs())));
+                state.events.publish(EventKind::SessionStopped {
+                    window_title: record.window.clone(),
+                    start: record.start_timestamp,
+                    end: record.end_timestamp,
+                    duration_secs: record.duration_secs(),
+                    pid: state.game_pid,
+                });
+                state.record_session(record);
+            }
+            state.game_running = false;
+            state.matched_window = None;
+            state.game_pid = None;
+            state.start_timestamp = None;
+            self.last_stop_time = now_ms;
+        } else if detection.found_game && state.game_running && matched_title.is_some() && matched_title != state.matched_window {
+            state.add_log(LogLevel::Game, format!("Game window changed: {}", matched_title.clone().unwrap_or_default()));
+            state.events.publish(EventKind::GameChanged { window_title: matched_title.clone() });
+            state.matched_window = matched_title;
+            state.game_pid = detection.matched_pid;
+        }
+    }
+}
+
+/// Starts an elevated instance that takes over from this one. The UAC prompt blocks,
+/// so it runs off the scan loop.
+fn request_elevation(app_state: Arc<Mutex<AppState>>) {
+    tokio::task::spawn_blocking(move || {
+        let (level, message) = match admin::run_as_admin(&["--takeover"]) {
+            Ok(()) => (LogLevel::Info, "Elevated instance started; handing over to it".to_string()),
+            Err(e) => (
+                LogLevel::Warning,
+                format!("Relaunch as administrator failed or was declined ({}); the game can't be detected until RPCS3 runs unelevated", e),
+            ),
+        };
+        app_state.lock().add_log(level, message);
+    });
+}
+
+/// Scans for the game and tells the Discord actor what to show. Never waits on
+/// Discord I/O, so scanning keeps its pace even while Discord is unresponsive.
+pub async fn run_background_tasks(
+    app_state: Arc<Mutex<AppState>>,
+    discord: DiscordHandle,
+    mut recorder: Option<TraceRecorder>,
+) {
+    let mut settings = app_state.lock().settings.clone();
     let mut scanner = ProcessScanner::new();
+    scanner.set_keywords(&settings.keywords);
+    let mut throttle = DebugThrottle::default();
+    let mut api_server = start_api(&app_state, &settings);
+    let mut presence_script = PresenceScript::new();
+    let elevated = admin::is_elevated();
+    let mut elevation = ElevationWatch::default();
 
-    let mut last_start_time = 0;
-    let mut last_stop_time = 0;
+    let mut detector = Detector::default();
+    let mut paused = false;
+    let mut requested: Option<Option<Presence>> = None;
 
     // Main Loop
     loop {
-        let debug_mode = app_state.lock().debug_mode;
+        let (debug_mode, updated) = {
+            let state = app_state.lock();
+            (state.debug_mode, state.settings.clone())
+        };
+        if updated != settings {
+            apply_settings(&app_state, &mut settings, updated, &mut scanner, &discord, &mut api_server);
+        }
+        if let Some(Err(e)) = recorder.as_mut().map(|r| r.record_settings(&settings)) {
+            app_state.lock().add_log(LogLevel::Error, format!("Trace recording stopped: {:#}", e));
+            recorder = None;
+        }
 
         // 1. Process Scan
          match scanner.scan(debug_mode) {
-            Ok((found_rpcs3, found_game, matched_title)) => {
+            Ok(detection) => {
+                let now = Utc::now().timestamp_millis();
+                // Written before taking the state lock, so file I/O never holds up the UI
+                let recorded = recorder.as_mut().map(|r| r.record_scan(now, scanner.last_duration(), scanner.last_seen()));
+
                 // Update Process Usage (CPU/RAM) BEFORE potential drops
                 let (cpu, ram) = scanner.get_own_usage();
 
                 let mut state = app_state.lock();
+                if let Some(Err(e)) = recorded {
+                    state.add_log(LogLevel::Error, format!("Trace recording stopped: {:#}", e));
+                    recorder = None;
+                }
                 state.cpu_usage = cpu;
                 state.ram_usage = ram;
-                
-                if debug_mode && found_rpcs3 {
-                    // Log debug info occasionally if needed, avoided for clutter
-                }
-
-                let now = Utc::now().timestamp_millis();
+                state.metrics.observe_scan(scanner.last_duration());
 
-                // Logic to update state
-                if found_game && !state.game_running {
-                    if now - last_start_time > START_COOLDOWN_MS {
-                        let msg = format!("MotorStorm Detected: {}", matched_title.clone().unwrap_or_default());
-                        state.add_log(LogLevel::Game, msg);
-                        state.game_running = true;
-                        state.start_timestamp = Some(Utc::now().timestamp());
-                        state.matched_window = matched_title;
-                        
-                        // Use the timestamp we just set
-                        let start_ts = state.start_timestamp.unwrap();
-                        
-                        last_start_time = now;
-                        
-                        // Update Discord
-                         drop(state); // Drop lock before IO
-                         
-                         // Try to update presence, if it fails, try to reconnect and update
-                         if let Err(e) = discord.update_presence(start_ts) {
-                              app_state.lock().add_log(LogLevel::Warning, format!("Initial presence update failed: {}", e));
-                              let _ = discord.connect(); // Try reconnect immediately
-                              let _ = discord.update_presence(start_ts); // Retry update
-                         }
+                if debug_mode {
+                    let report = scanner.last_report();
+                    let summary: Vec<_> = report
+                        .candidates
+                        .iter()
+                        .map(|c| (c.pid, c.titles.as_ref().map(|t| t.iter().map(|t| t.title.clone()).collect::<Vec<_>>())))
+                        .collect();
+                    if throttle.allow("scan", &summary) {
+                        log_scan_report(&mut state, report);
                     }
-                } else if (!found_rpcs3 && state.game_running) || (!found_game && state.game_running && found_rpcs3) {
-                     if now - last_stop_time > STOP_COOLDOWN_MS {
-                         if !found_rpcs3 {
-                             state.add_log(LogLevel::Game, "RPCS3 process closed".to_string());
-                         } else {
-                              state.add_log(LogLevel::Game, "Game window no longer active".to_string());
-                         }
-                         state.game_running = false;
-                         state.matched_window = None;
-                         state.start_timestamp = None;
-                         last_stop_time = now;
-
-                         // Clear Discord
-                         drop(state);
-                         
-                         if let Err(e) = discord.clear_presence() {
-                             // Non-fatal, but log it
-                             if debug_mode {
-                                 app_state.lock().add_log(LogLevel::Error, format!("Clear presence failed: {}", e));
-                             }
-                         }
-                     }
-                } else {
-                     // Update connection status in UI
-                     state.discord_connected = discord.is_connected();
                 }
 
+                detector.step(&mut state, &settings, &detection, now, debug_mode);
 
+                // elevation = if-needed: an elevated emulator hides its windows from us
+                if !elevated && state.elevation() == ElevationPolicy::IfNeeded {
+                    if let Some(pid) = elevation.observe(detection.unreadable_pid, now) {
+                        state.add_log(
+                            LogLevel::Warning,
+                            format!(
+                                "RPCS3 (pid {}) has refused access to its window titles for {} s; it probably runs as administrator, so asking to relaunch elevated (elevation 'if-needed')",
+                                pid,
+                                ELEVATION_GRACE_MS / 1000
+                            ),
+                        );
+                        request_elevation(app_state.clone());
+                    }
+                }
             }
             Err(e) => {
                 app_state.lock().add_log(LogLevel::Error, format!("Scan error: {}", e));
             }
         }
 
-        // Reconnect logic if disconnected
-        if !discord.is_connected() {
-             if let Ok(_) = discord.connect() {
-                 app_state.lock().add_log(LogLevel::Success, "Reconnected to Discord".to_string());
-             }
+        // 2. Privacy mode: the actor withholds updates while paused
+        let now_paused = app_state.lock().presence_paused;
+        if now_paused != paused {
+            discord.send(if now_paused { DiscordCommand::Pause } else { DiscordCommand::Resume });
+            paused = now_paused;
+        }
+
+        // 3. Queue presence changes (session start/stop, override, template edits, scripts)
+        let (desired, ctx) = {
+            let state = app_state.lock();
+            let desired = desired_presence(&state, &settings);
+            // A manual override is never scripted
+            let ctx = desired.as_ref().filter(|_| state.manual_override.is_none()).map(|d| script::context(&state, d));
+            (desired, ctx)
+        };
+        // Time-limited, and run without holding the state lock
+        let desired = match (desired, ctx) {
+            (Some(default), Some(ctx)) => {
+                let (presence, logs) = presence_script.presence(&settings.presence_script, ctx, default);
+                let mut state = app_state.lock();
+                for (level, message) in logs {
+                    state.add_log(level, message);
+                }
+                Some(presence)
+            }
+            (desired, _) => desired,
+        };
+        if requested.as_ref() != Some(&desired) {
+            discord.send(DiscordCommand::SetActivity(desired.clone()));
+            requested = Some(desired);
+        }
+
+        // 4. Mirror the actor's status for the UI and announce connection changes
+        {
+            let status = discord.status();
+            let mut state = app_state.lock();
+            if status.connected != state.discord.connected {
+                state.events.publish(if status.connected {
+                    EventKind::DiscordConnected {
+                        user: status.user.as_ref().map(|u| u.display_name().to_string()),
+                        endpoint: status.endpoint.as_ref().map(|e| e.path.clone()),
+                    }
+                } else {
+                    EventKind::DiscordDisconnected
+                });
+            }
+            state.discord = status;
         }
 
-        sleep(Duration::from_millis(PROCESS_CHECK_INTERVAL_MS)).await;
+        sleep(Duration::from_millis(settings.poll_interval_ms)).await;
     }
 }
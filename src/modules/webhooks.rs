@@ -0,0 +1,233 @@
+//! Outgoing webhooks: POSTs a templated JSON body to each configured target
+//! whose event filter matches, retrying failed deliveries with backoff.
+
+use crate::modules::app::{AppState, LogLevel};
+use crate::modules::config::{
+    WebhookTarget, GAME_NAME, WEBHOOK_MAX_ATTEMPTS, WEBHOOK_RETRY_BASE_MS, WEBHOOK_TIMEOUT_MS,
+};
+use crate::modules::events::EventKind;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::sleep;
+
+/// Values used to check a template when settings are validated.
+pub fn sample_vars() -> Vec<(&'static str, String)> {
+    vec![
+        ("event", "session_stopped".to_string()),
+        ("game", GAME_NAME.to_string()),
+        ("title_id", "BCES00129".to_string()),
+        ("pid", "4242".to_string()),
+        ("user", "Racer".to_string()),
+        ("duration", "00:42:00".to_string()),
+        ("duration_secs", "2520".to_string()),
+        ("at", "1760000000".to_string()),
+    ]
+}
+
+/// Substitutes `{name}` placeholders, escaping values so the result stays valid JSON,
+/// and parses the result.
+pub fn render_body(template: &str, vars: &[(&str, String)]) -> Result<Value, String> {
+    let mut body = template.to_string();
+    for (name, value) in vars {
+        let quoted = Value::String(value.clone()).to_string();
+        body = body.replace(&format!("{{{}}}", name), &quoted[1..quoted.len() - 1]);
+    }
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
+
+/// POSTs `body` to `url`, retrying network errors, 429 and 5xx with doubling delays.
+/// Returns the final HTTP status, or the last error.
+async fn deliver(url: String, body: Value, retry_base: Duration) -> Result<u16, String> {
+    let mut delay = retry_base;
+    let mut attempt = 1;
+    loop {
+        let (url_clone, body_clone) = (url.clone(), body.clone());
+        // Ok(status), or Err((reason, retryable))
+        let result = tokio::task::spawn_blocking(move || {
+            match ureq::post(&url_clone).timeout(Duration::from_millis(WEBHOOK_TIMEOUT_MS)).send_json(body_clone) {
+                Ok(response) => Ok(response.status()),
+                Err(ureq::Error::Status(code, _)) => Err((format!("HTTP {}", code), code == 429 || code >= 500)),
+                Err(e) => Err((e.to_string(), true)),
+            }
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let reason = match result {
+            Ok(code) => return Ok(code),
+            Err((reason, false)) => return Err(reason),
+            Err((reason, true)) => reason,
+        };
+        if attempt >= WEBHOOK_MAX_ATTEMPTS {
+            return Err(format!("{} (after {} attempts)", reason, attempt));
+        }
+        sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Delivers events to the configured webhooks until the event bus closes.
+pub async fn run_webhooks(app_state: Arc<Mutex<AppState>>) {
+    run_with_retry_base(app_state, Duration::from_millis(WEBHOOK_RETRY_BASE_MS)).await
+}
+
+async fn run_with_retry_base(app_state: Arc<Mutex<AppState>>, retry_base: Duration) {
+    let mut events = app_state.lock().events.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                app_state.lock().add_log(LogLevel::Warning, format!("Webhooks fell behind, {} event(s) not delivered", skipped));
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // Log entries are never sent; delivery results are logged themselves
+        if matches!(event.kind, EventKind::Log { .. }) {
+            continue;
+        }
+
+        let deliveries: Vec<(WebhookTarget, Result<Value, String>)> = {
+            let state = app_state.lock();
+            let vars = event.vars(&state);
+            state
+                .settings
+                .webhooks
+                .iter()
+                .filter(|t| t.events.iter().any(|e| e == event.kind.name()))
+                .map(|t| (t.clone(), render_body(&t.body, &vars)))
+                .collect()
+        };
+
+        for (target, body) in deliveries {
+            let name = event.kind.name();
+            let body = match body {
+                Ok(body) => body,
+                Err(e) => {
+                    app_state.lock().add_log(LogLevel::Error, format!("Webhook {}: invalid body: {}", target.url, e));
+                    continue;
+                }
+            };
+            let app_state = app_state.clone();
+            tokio::spawn(async move {
+                let result = deliver(target.url.clone(), body, retry_base).await;
+                let mut state = app_state.lock();
+                match result {
+                    Ok(code) => state.add_log(LogLevel::Success, format!("Webhook {}: delivered {} (HTTP {})", target.url, name, code)),
+                    Err(e) => state.add_log(LogLevel::Error, format!("Webhook {}: failed to deliver {}: {}", target.url, name, e)),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::Settings;
+    use crate::modules::mock_http::MockHttp;
+
+    const WAIT: Duration = Duration::from_secs(3);
+
+    fn target(mock: &MockHttp, events: &[&str], body: &str) -> WebhookTarget {
+        WebhookTarget {
+            url: mock.url("/hook"),
+            events: events.iter().map(|e| e.to_string()).collect(),
+            body: body.to_string(),
+        }
+    }
+
+    async fn wait_for_log(app_state: &Arc<Mutex<AppState>>, needle: &str) -> bool {
+        for _ in 0..300 {
+            if app_state.lock().logs.iter().any(|l| l.message.contains(needle)) {
+                return true;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[test]
+    fn body_values_are_escaped() {
+        let vars = vec![("game", "Race \"Pacific\"".to_string()), ("duration_secs", "90".to_string())];
+        let body = render_body(r#"{"text": "{game}", "secs": {duration_secs}}"#, &vars).unwrap();
+        assert_eq!(body["text"], "Race \"Pacific\"");
+        assert_eq!(body["secs"], 90);
+    }
+
+    #[test]
+    fn targets_round_trip_through_the_config_file() {
+        let settings = Settings {
+            webhooks: vec![WebhookTarget { url: "http://127.0.0.1:9000/hook".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+
+        let text = toml::to_string_pretty(&settings).unwrap();
+        assert!(text.contains("[[webhooks]]"));
+        let loaded: Settings = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.webhooks, settings.webhooks);
+        assert!(loaded.validate().is_ok());
+
+        let bad: Settings =
+            toml::from_str("[[webhooks]]\nurl = \"http://x\"\nevents = [\"log\"]\n").unwrap();
+        assert!(bad.validate().unwrap_err().contains("Unknown webhook event 'log'"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn session_events_are_posted_to_matching_targets() {
+        let stopped = MockHttp::start("{}");
+        let started_only = MockHttp::start("{}");
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        app_state.lock().settings.webhooks = vec![
+            target(&stopped, &["session_stopped"], r#"{"text": "{game} for {duration}", "secs": {duration_secs}, "pid": {pid}}"#),
+            target(&started_only, &["session_started"], "{}"),
+        ];
+        tokio::spawn(run_with_retry_base(app_state.clone(), Duration::from_millis(10)));
+        sleep(Duration::from_millis(50)).await;
+
+        app_state.lock().events.publish(EventKind::SessionStopped {
+            window_title: Some("MotorStorm".to_string()),
+            start: 0,
+            end: 125,
+            duration_secs: 125,
+            pid: Some(4242),
+        });
+
+        let requests = stopped.wait_for_requests(1, WAIT);
+        assert_eq!(requests[0].method, "POST");
+        let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["text"], "MotorStorm for 00:02:05");
+        assert_eq!(body["secs"], 125);
+        assert_eq!(body["pid"], 4242);
+        assert!(wait_for_log(&app_state, "delivered session_stopped").await);
+        assert!(started_only.requests().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_deliveries_are_retried_then_reported() {
+        let flaky = MockHttp::start("{}");
+        flaky.respond(503, "");
+        let broken = MockHttp::start("");
+        for _ in 0..WEBHOOK_MAX_ATTEMPTS {
+            broken.respond(500, "");
+        }
+        let app_state = Arc::new(Mutex::new(AppState::new()));
+        app_state.lock().settings.webhooks = vec![
+            target(&flaky, &["presence_paused"], "{}"),
+            WebhookTarget { url: broken.url("/broken"), ..target(&broken, &["presence_paused"], "{}") },
+        ];
+        tokio::spawn(run_with_retry_base(app_state.clone(), Duration::from_millis(10)));
+        sleep(Duration::from_millis(50)).await;
+
+        app_state.lock().set_paused(true);
+
+        assert_eq!(flaky.wait_for_requests(2, WAIT).len(), 2);
+        assert!(wait_for_log(&app_state, &format!("Webhook {}: delivered presence_paused", flaky.url("/hook"))).await);
+        assert_eq!(broken.wait_for_requests(WEBHOOK_MAX_ATTEMPTS as usize, WAIT).len(), WEBHOOK_MAX_ATTEMPTS as usize);
+        assert!(wait_for_log(&app_state, "HTTP 500 (after 4 attempts)").await);
+    }
+}
@@ -6,13 +6,14 @@ use windows::{
     Win32::Foundation::{LPARAM, WPARAM},
 
     Win32::UI::WindowsAndMessaging::{
-        LoadImageW, SendMessageW,
-        IMAGE_ICON, LR_DEFAULTSIZE, LR_LOADFROMFILE, WM_SETICON, ICON_BIG, ICON_SMALL,
+        AllowSetForegroundWindow, IsIconic, LoadImageW, SendMessageW, SetForegroundWindow, ShowWindow,
+        ASFW_ANY, IMAGE_ICON, SW_RESTORE, LR_DEFAULTSIZE, LR_LOADFROMFILE, WM_SETICON, ICON_BIG, ICON_SMALL,
     },
     Win32::System::Console::{GetConsoleWindow, SetConsoleTitleW},
 };
 
 // Set Console Title
+#[cfg_attr(not(windows), allow(unused_variables))]
 pub fn set_console_title(title: &str) {
     #[cfg(windows)]
     unsafe {
@@ -49,3 +50,26 @@ pub fn set_console_icon() -> Result<()> {
     }
     Ok(())
 }
+
+// Raise our console window (used when a second launch is redirected here)
+pub fn bring_console_to_front() {
+    #[cfg(windows)]
+    unsafe {
+        let hwnd = GetConsoleWindow();
+        if hwnd.0 == 0 {
+            return;
+        }
+        if IsIconic(hwnd).as_bool() {
+            ShowWindow(hwnd, SW_RESTORE);
+        }
+        SetForegroundWindow(hwnd);
+    }
+}
+
+// Let the running instance take the foreground from us (called by the second launch)
+pub fn allow_foreground_handoff() {
+    #[cfg(windows)]
+    unsafe {
+        let _ = AllowSetForegroundWindow(ASFW_ANY);
+    }
+}